@@ -0,0 +1,313 @@
+use candid::Principal;
+use clap::Args;
+use ic_agent::{identity::Secp256k1Identity, Identity};
+use icp_ledger::AccountIdentifier;
+use k256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+    sha2::{Digest, Sha256},
+    PublicKey,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tracing::info;
+
+use crate::error::{OracleError, Result};
+use crate::file;
+
+// Prefixed to every request before signing, so an approval can't be passed off as a signature
+// over anything else.
+const DOMAIN: &[u8] = b"\x0foracle-approval";
+
+#[derive(Args, Debug, Clone)]
+pub struct ApprovalArgs {
+    /// Principal of an operator who can approve high-value operations. May be repeated. With no
+    /// operators, nothing needs approval
+    #[arg(
+        long = "approval-operator",
+        env = "ORACLE_APPROVAL_OPERATORS",
+        value_delimiter = ','
+    )]
+    pub approval_operators: Vec<String>,
+
+    /// Number of operators who must approve an operation before it is submitted
+    #[arg(long, env = "ORACLE_APPROVAL_REQUIRED", default_value = "2")]
+    pub approval_required: usize,
+
+    /// Splits larger than this need approval. If unset, splits never need approval
+    #[arg(long, env = "ORACLE_APPROVAL_THRESHOLD_E8S")]
+    pub approval_threshold_e8s: Option<u64>,
+
+    /// Directory to write approval requests to, and read approvals from
+    #[arg(long, env = "ORACLE_APPROVAL_DIR", default_value = "approvals")]
+    pub approval_dir: PathBuf,
+
+    /// How long an approval request stays valid for, in seconds
+    #[arg(long, env = "ORACLE_APPROVAL_TTL_SECS", default_value = "86400")]
+    pub approval_ttl_secs: u64,
+}
+
+impl ApprovalArgs {
+    // The approval policy, with disburses to `deposits_address` exempt.
+    pub fn policy(&self, deposits_address: AccountIdentifier) -> Result<Policy> {
+        let operators = self
+            .approval_operators
+            .iter()
+            .map(|p| {
+                Principal::from_text(p)
+                    .map_err(|e| OracleError::Config(format!("Bad approval operator {p}: {e}")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !operators.is_empty()
+            && (self.approval_required == 0 || self.approval_required > operators.len())
+        {
+            return Err(OracleError::Config(format!(
+                "--approval-required must be between 1 and the number of operators ({}), got {}",
+                operators.len(),
+                self.approval_required
+            )));
+        }
+        Ok(Policy {
+            operators,
+            required: self.approval_required,
+            threshold_e8s: self.approval_threshold_e8s,
+            dir: self.approval_dir.clone(),
+            ttl_secs: self.approval_ttl_secs,
+            deposits_address: Some(deposits_address),
+        })
+    }
+}
+
+// A governance operation which may need approval.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum Operation {
+    Split { neuron_id: u64, amount_e8s: u64 },
+    Disburse { neuron_id: u64, to: String },
+}
+
+impl Operation {
+    // Stable name for the operation, used to name its request file.
+    fn id(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_vec(self).unwrap_or_default());
+        hex::encode(&hasher.finalize()[..16])
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Split {
+                neuron_id,
+                amount_e8s,
+            } => write!(f, "split {} e8s off neuron {}", amount_e8s, neuron_id),
+            Operation::Disburse { neuron_id, to } => {
+                write!(f, "disburse neuron {} to {}", neuron_id, to)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Request {
+    #[serde(flatten)]
+    pub operation: Operation,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+impl Request {
+    // The bytes operators sign.
+    fn message(&self) -> Vec<u8> {
+        let mut message = DOMAIN.to_vec();
+        message.extend(serde_json::to_vec(self).unwrap_or_default());
+        message
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Approval {
+    pub principal: String,
+    // DER-encoded secp256k1 public key, hex.
+    pub public_key: String,
+    // (r, s) signature over the request, hex.
+    pub signature: String,
+}
+
+impl Approval {
+    // The approving operator, if the signature is valid.
+    fn verify(&self, request: &Request) -> Option<Principal> {
+        let public_key = hex::decode(&self.public_key).ok()?;
+        let signature = Signature::try_from(hex::decode(&self.signature).ok()?.as_slice()).ok()?;
+        let key = VerifyingKey::from(&PublicKey::from_public_key_der(&public_key).ok()?);
+        key.verify(&request.message(), &signature).ok()?;
+        let principal = Principal::self_authenticating(&public_key);
+        (principal.to_string() == self.principal).then_some(principal)
+    }
+}
+
+// An approval request, and the approvals collected for it so far. Operators pass the file around,
+// each adding their approval with `oracle approve`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RequestFile {
+    pub request: Request,
+    #[serde(default)]
+    pub approvals: Vec<Approval>,
+}
+
+impl RequestFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read(path)
+            .map_err(|e| OracleError::Approval(format!("Couldn't read {}: {e}", path.display())))?;
+        serde_json::from_slice(&contents)
+            .map_err(|e| OracleError::Approval(format!("Couldn't parse {}: {e}", path.display())))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_vec_pretty(self)
+            .map_err(|e| OracleError::Approval(format!("Couldn't encode {}: {e}", path.display())))?;
+        file::write_atomic(path, &contents)
+            .map_err(|e| OracleError::Approval(format!("Couldn't write {}: {e}", path.display())))
+    }
+
+    // Add an approval signed with the operator's key, replacing any previous approval by them.
+    pub fn approve(&mut self, operator: &Secp256k1Identity) -> Result<Principal> {
+        let principal = operator.sender().map_err(OracleError::Signer)?;
+        let signature = operator
+            .sign(&self.request.message())
+            .map_err(OracleError::Signer)?;
+        let (Some(public_key), Some(signature)) = (signature.public_key, signature.signature)
+        else {
+            return Err(OracleError::Signer(
+                "operator key produced no signature".to_string(),
+            ));
+        };
+        self.approvals
+            .retain(|a| a.principal != principal.to_string());
+        self.approvals.push(Approval {
+            principal: principal.to_string(),
+            public_key: hex::encode(public_key),
+            signature: hex::encode(signature),
+        });
+        Ok(principal)
+    }
+
+    // The distinct operators with a valid approval on the request.
+    pub fn approved_by(&self, operators: &[Principal]) -> Vec<Principal> {
+        let mut approved: Vec<Principal> = vec![];
+        for principal in self
+            .approvals
+            .iter()
+            .filter_map(|a| a.verify(&self.request))
+        {
+            if operators.contains(&principal) && !approved.contains(&principal) {
+                approved.push(principal);
+            }
+        }
+        approved
+    }
+}
+
+// Which governance operations need N of M operator approvals before they are submitted.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    pub operators: Vec<Principal>,
+    pub required: usize,
+    // Splits larger than this need approval.
+    pub threshold_e8s: Option<u64>,
+    pub dir: PathBuf,
+    pub ttl_secs: u64,
+    // Disbursing anywhere else needs approval.
+    pub deposits_address: Option<AccountIdentifier>,
+}
+
+impl Policy {
+    pub fn needs_approval(&self, operation: &Operation) -> bool {
+        if self.operators.is_empty() {
+            return false;
+        }
+        match operation {
+            Operation::Split { amount_e8s, .. } => {
+                self.threshold_e8s.map_or(false, |t| *amount_e8s > t)
+            }
+            Operation::Disburse { to, .. } => {
+                self.deposits_address.as_ref().map(|a| a.to_hex()).as_ref() != Some(to)
+            }
+        }
+    }
+
+    pub fn request_path(&self, operation: &Operation) -> PathBuf {
+        self.dir.join(format!("{}.json", operation.id()))
+    }
+
+    // Check the operation may be submitted. If it needs approval and doesn't have enough yet, a
+    // request file is written (if there isn't a current one already) for the operators to approve,
+    // and an ApprovalRequired error returned.
+    pub fn check(&self, operation: &Operation) -> Result<()> {
+        if !self.needs_approval(operation) {
+            return Ok(());
+        }
+        let path = self.request_path(operation);
+        let now = now()?;
+        let file = match path.exists() {
+            true => Some(RequestFile::load(&path)?),
+            false => None,
+        };
+        let file = match file {
+            Some(file) if file.request.operation == *operation && file.request.expires_at > now => {
+                file
+            }
+            // Missing or expired, so start collecting approvals afresh.
+            _ => {
+                fs::create_dir_all(&self.dir).map_err(|e| {
+                    OracleError::Approval(format!("Couldn't create {}: {e}", self.dir.display()))
+                })?;
+                let file = RequestFile {
+                    request: Request {
+                        operation: operation.clone(),
+                        created_at: now,
+                        expires_at: now + self.ttl_secs,
+                    },
+                    approvals: vec![],
+                };
+                file.save(&path)?;
+                info!(request = %path.display(), %operation, "Wrote approval request");
+                file
+            }
+        };
+
+        let approvals = file.approved_by(&self.operators).len();
+        if approvals < self.required {
+            return Err(OracleError::ApprovalRequired {
+                request: path,
+                approvals,
+                required: self.required,
+            });
+        }
+        Ok(())
+    }
+
+    // Mark an approved operation as submitted, so the approval can't be used again.
+    pub fn consume(&self, operation: &Operation) -> Result<()> {
+        if !self.needs_approval(operation) {
+            return Ok(());
+        }
+        let path = self.request_path(operation);
+        let used = path.with_extension(format!("used-{}.json", now()?));
+        fs::rename(&path, &used).map_err(|e| {
+            OracleError::Approval(format!("Couldn't mark {} as used: {e}", path.display()))
+        })
+    }
+}
+
+fn now() -> Result<u64> {
+    Ok(SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|e| OracleError::Config(e.to_string()))?
+        .as_secs())
+}
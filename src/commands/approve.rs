@@ -0,0 +1,47 @@
+use anyhow::{bail, Context};
+use clap::Args;
+use ic_agent::identity::Secp256k1Identity;
+use std::{path::PathBuf, time::SystemTime};
+
+use crate::approval::RequestFile;
+use crate::identity;
+
+#[derive(Args, Debug)]
+pub struct Command {
+    /// Approval request file written by the oracle
+    request: PathBuf,
+
+    /// The operator's secp256k1 PEM key file, or - to read it from STDIN
+    #[arg(long)]
+    private_pem: PathBuf,
+}
+
+impl Command {
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let pem = identity::read_file(&self.private_pem, "PEM")?;
+        let operator = Secp256k1Identity::from_pem(pem.as_bytes())
+            .context("couldn't load operator key, only secp256k1 keys are supported")?;
+
+        let mut file = RequestFile::load(&self.request)?;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        if file.request.expires_at <= now {
+            bail!(
+                "Request {} expired at {}",
+                self.request.display(),
+                file.request.expires_at
+            );
+        }
+
+        println!("Approving: {}", file.request.operation);
+        let principal = file.approve(&operator)?;
+        file.save(&self.request)?;
+        println!(
+            "Approved as {}, {} approvals in total",
+            principal,
+            file.approvals.len()
+        );
+        Ok(())
+    }
+}
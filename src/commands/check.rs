@@ -0,0 +1,270 @@
+use anyhow::{bail, Context};
+use candid::Principal;
+use clap::Args;
+use ic_agent::Agent;
+use ic_base_types::PrincipalId;
+use ic_nns_governance::pb::v1::Neuron;
+use icp_ledger::AccountIdentifier;
+
+use crate::deposits::{self, Service as DepositsService};
+use crate::governance::{self, Service as GovernanceService};
+use crate::identity;
+use crate::ledger::{
+    self,
+    icrc::{self, Service as IcrcService},
+    Service as LedgerService,
+};
+use crate::query::ReadMode;
+
+#[derive(Args, Debug)]
+pub struct Command {
+    #[command(flatten)]
+    identity: identity::IdentityArgs,
+
+    #[command(flatten)]
+    ledger: ledger::LedgerArgs,
+
+    /// Fail if the deposits account holds less than this
+    #[arg(long, default_value = "0")]
+    min_balance_e8s: u64,
+}
+
+// The outcome of one check, as a row of the report.
+struct Check {
+    name: &'static str,
+    result: Result<String, String>,
+}
+
+impl Check {
+    fn new(name: &'static str, result: anyhow::Result<String>) -> Self {
+        Self {
+            name,
+            result: result.map_err(|e| format!("{:#}", e)),
+        }
+    }
+
+    fn skipped(name: &'static str, because: &str) -> Self {
+        Self {
+            name,
+            result: Err(format!("skipped, {} failed", because)),
+        }
+    }
+}
+
+impl Command {
+    // Validate the configuration end to end, without mutating anything.
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let mut checks = vec![];
+
+        let principal = self.identity.principal().await;
+        let signer_ok = principal.is_ok();
+        checks.push(Check::new(
+            "signer principal",
+            principal
+                .as_ref()
+                .map(|p| p.to_string())
+                .map_err(|e| anyhow::anyhow!("{:#}", e)),
+        ));
+
+        checks.push(if signer_ok {
+            Check::new(
+                "signer public_key",
+                self.identity.fetch_principal().await.and_then(|fetched| {
+                    if Some(&fetched) != principal.as_ref().ok() {
+                        bail!("signer now has principal {}, not the cached key's", fetched);
+                    }
+                    Ok("answering".to_string())
+                }),
+            )
+        } else {
+            Check::skipped("signer public_key", "signer principal")
+        });
+
+        // Without an agent every call below fails, but each is still reported.
+        let local_agent = self
+            .identity
+            .create_local_agent()
+            .await
+            .map_err(|e| format!("couldn't create agent: {:#}", e));
+
+        let neurons = match &local_agent {
+            Ok(agent) => self.deposits_neurons(agent).await,
+            Err(e) => Err(anyhow::anyhow!("{}", e)),
+        };
+        checks.push(Check::new(
+            "deposits canister",
+            neurons
+                .as_ref()
+                .map(|ids| format!("reachable, tracking {} neurons", ids.len()))
+                .map_err(|e| anyhow::anyhow!("{:#}", e)),
+        ));
+
+        match (&principal, &neurons) {
+            (Ok(principal), Ok(neurons)) => {
+                let listed = self.governance_neurons(neurons.clone()).await;
+                checks.push(Check::new(
+                    "governance canister",
+                    listed
+                        .as_ref()
+                        .map(|n| format!("reachable, {} neurons readable", n.len()))
+                        .map_err(|e| anyhow::anyhow!("{:#}", e)),
+                ));
+                checks.push(match listed {
+                    Ok(listed) => Check::new("neuron control", {
+                        let signer = PrincipalId(*principal);
+                        let uncontrolled: Vec<String> = neurons
+                            .iter()
+                            .filter(|id| {
+                                !listed.iter().any(|n| {
+                                    n.id.as_ref().map(|n| n.id) == Some(**id)
+                                        && (n.controller == Some(signer)
+                                            || n.hot_keys.contains(&signer))
+                                })
+                            })
+                            .map(|id| id.to_string())
+                            .collect();
+                        if uncontrolled.is_empty() {
+                            Ok(format!(
+                                "controller or hotkey of all {} neurons",
+                                neurons.len()
+                            ))
+                        } else {
+                            Err(anyhow::anyhow!(
+                                "not controller or hotkey of neurons {}",
+                                uncontrolled.join(", ")
+                            ))
+                        }
+                    }),
+                    Err(_) => Check::skipped("neuron control", "governance canister"),
+                });
+            }
+            _ => {
+                checks.push(Check::skipped(
+                    "governance canister",
+                    "signer principal or deposits canister",
+                ));
+                checks.push(Check::skipped(
+                    "neuron control",
+                    "signer principal or deposits canister",
+                ));
+            }
+        }
+
+        let balance = match &local_agent {
+            Ok(agent) => self.deposits_balance(agent).await,
+            Err(e) => Err(anyhow::anyhow!("{}", e)),
+        };
+        checks.push(Check::new(
+            "deposits balance",
+            match balance {
+                Ok(e8s) if e8s < self.min_balance_e8s => Err(anyhow::anyhow!(
+                    "{} e8s, below minimum of {} e8s",
+                    e8s,
+                    self.min_balance_e8s
+                )),
+                Ok(e8s) => Ok(format!("{} e8s", e8s)),
+                Err(e) => Err(e),
+            },
+        ));
+
+        if self.ledger.sticp_ledger.is_some() {
+            let fee = match &local_agent {
+                Ok(agent) => self.sticp_fee(agent).await,
+                Err(e) => Err(anyhow::anyhow!("{}", e)),
+            };
+            checks.push(Check::new(
+                "stICP ledger",
+                fee.map(|fee| format!("reachable, fee {} e8s", fee)),
+            ));
+        }
+
+        let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        for check in checks.iter() {
+            let (status, detail) = match &check.result {
+                Ok(detail) => ("PASS", detail),
+                Err(detail) => ("FAIL", detail),
+            };
+            println!(
+                "{:width$}  {}  {}",
+                check.name,
+                status,
+                detail,
+                width = width
+            );
+        }
+
+        let failed = checks.iter().filter(|c| c.result.is_err()).count();
+        if failed > 0 {
+            bail!("{} of {} checks failed", failed, checks.len());
+        }
+        Ok(())
+    }
+
+    async fn deposits_neurons(&self, agent: &Agent) -> anyhow::Result<Vec<u64>> {
+        let d = deposits::Agent {
+            agent,
+            canister_id: Principal::from_text(&self.identity.deposits_canister)?,
+            retry: self.identity.retry.policy(),
+        };
+        Ok(d.list_neurons(ReadMode::Query).await?)
+    }
+
+    // Read through the signer, so this also checks the signer can sign calls.
+    async fn governance_neurons(&self, ids: Vec<u64>) -> anyhow::Result<Vec<Neuron>> {
+        let agent = self
+            .identity
+            .create_agent()
+            .await
+            .context("couldn't create agent")?;
+        let g = governance::Agent {
+            agent: &agent,
+            canister_id: Principal::from_text(&self.identity.governance)?,
+            retry: self.identity.retry.policy(),
+            approvals: self.identity.approval_policy()?,
+            disburse_safety: self.identity.disburse_safety()?,
+        };
+        Ok(g.list_neurons(ids, ReadMode::Query).await?)
+    }
+
+    async fn deposits_balance(&self, agent: &Agent) -> anyhow::Result<u64> {
+        let deposits = Principal::from_text(&self.identity.deposits_canister)?;
+        Ok(match self.ledger.icp_ledger_interface {
+            ledger::Interface::Legacy => {
+                let icp = ledger::Agent {
+                    agent,
+                    canister_id: self.ledger.icp_ledger()?,
+                    retry: self.identity.retry.policy(),
+                };
+                icp.account_balance(
+                    AccountIdentifier::new(PrincipalId(deposits), None),
+                    ReadMode::Query,
+                )
+                .await?
+            }
+            ledger::Interface::Icrc1 => {
+                let icp = icrc::Agent {
+                    agent,
+                    canister_id: self.ledger.icp_ledger()?,
+                    retry: self.identity.retry.policy(),
+                };
+                icp.balance_of(
+                    &icrc::Account {
+                        owner: deposits,
+                        subaccount: None,
+                    },
+                    ReadMode::Query,
+                )
+                .await?
+            }
+        })
+    }
+
+    async fn sticp_fee(&self, agent: &Agent) -> anyhow::Result<u64> {
+        let sticp = icrc::Agent {
+            agent,
+            canister_id: self.ledger.sticp_ledger()?,
+            retry: self.identity.retry.policy(),
+        };
+        Ok(sticp.fee(ReadMode::Query).await?)
+    }
+}
@@ -0,0 +1,95 @@
+use clap::{parser::ValueSource, ArgAction, ArgMatches, Args, Subcommand};
+
+use crate::config::Config;
+use crate::identity;
+
+#[derive(Args, Debug)]
+pub struct Command {
+    #[command(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective settings, after layering the config file, environment variables and
+    /// flags
+    Show(Show),
+}
+
+#[derive(Args, Debug)]
+struct Show {
+    #[command(flatten)]
+    identity: identity::IdentityArgs,
+}
+
+impl Command {
+    // Needs the parsed matches, as well as the settings, to tell where each one came from.
+    pub fn run(&self, config: &Config, matches: &ArgMatches) -> anyhow::Result<()> {
+        match &self.command {
+            ConfigCommand::Show(_) => {
+                let matches = matches
+                    .subcommand_matches("config")
+                    .and_then(|m| m.subcommand_matches("show"))
+                    .unwrap_or(matches);
+                show(config, matches);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Print every setting as TOML, with where its value came from: a flag, the environment, the
+// config file, or the built-in default.
+fn show(config: &Config, matches: &ArgMatches) {
+    match &config.path {
+        Some(path) => println!("# Config file: {}", path.display()),
+        None => println!("# No config file"),
+    }
+    if let Some(profile) = &config.profile {
+        println!("# Profile: {}", profile);
+    }
+
+    let args = Show::augment_args(clap::Command::new("show"));
+    let mut used = vec![];
+    for arg in args.get_arguments() {
+        let id = arg.get_id().as_str();
+        let Some(values) = matches.get_raw(id) else {
+            continue;
+        };
+        let values: Vec<String> = values.map(|v| v.to_string_lossy().into_owned()).collect();
+        let setting = config.setting_for(arg);
+        used.extend(setting);
+        let source = match matches.value_source(id) {
+            Some(ValueSource::CommandLine) => "flag",
+            Some(ValueSource::EnvVariable) => "environment",
+            _ if setting.is_some() => "config",
+            _ => "default",
+        };
+        let many = matches!(arg.get_action(), ArgAction::Append);
+        println!("{} = {} # {}", id, to_toml(values, many), source);
+    }
+
+    let others: Vec<_> = config
+        .settings
+        .iter()
+        .filter(|(key, _)| !used.contains(&key.as_str()))
+        .collect();
+    if !others.is_empty() {
+        println!();
+        println!("# Settings for other commands");
+        for (key, values) in others {
+            println!(
+                "{} = {} # config",
+                key,
+                to_toml(values.clone(), values.len() != 1)
+            );
+        }
+    }
+}
+
+fn to_toml(mut values: Vec<String>, many: bool) -> toml::Value {
+    if many {
+        return toml::Value::Array(values.into_iter().map(toml::Value::String).collect());
+    }
+    toml::Value::String(values.pop().unwrap_or_default())
+}
@@ -1,57 +1,478 @@
+use anyhow::bail;
 use candid::Principal;
 use clap::Args;
-use std::time::SystemTime;
+use icp_ledger::AccountIdentifier;
+use std::{path::PathBuf, time::SystemTime};
+use tracing::{info, info_span, warn, Instrument};
 
+use crate::alert;
+use crate::approval::Operation;
 use crate::deposits::{self, Service as DepositsService};
 use crate::governance::{self, Service as GovernanceService};
 use crate::identity;
+use crate::journal::{Journal, Run, Split, SplitStep};
+use crate::metrics;
+use crate::query::ReadMode;
+use crate::strategy::{self, Review, Strategy};
 
 #[derive(Args, Debug)]
 pub struct Command {
     #[command(flatten)]
     identity: identity::IdentityArgs,
+
+    /// File to record the progress of the daily run in. If a previous run was interrupted, it will
+    /// be resumed from here.
+    #[arg(long, env = "ORACLE_JOURNAL", default_value = "oracle-journal.json")]
+    pub journal: PathBuf,
+
+    /// Print what the run would do, without making any changes to neurons or the deposits
+    /// canister.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// How to read the list of neurons to disburse. Certified reads go through consensus, so
+    /// can't be faked by a single replica, but are slower
+    #[arg(long, value_enum, default_value = "certified")]
+    disburse_reads: ReadMode,
+
+    /// File to write metrics to after the run, for node_exporter's textfile collector
+    #[arg(long, env = "ORACLE_METRICS_TEXTFILE")]
+    metrics_textfile: Option<PathBuf>,
+
+    #[command(flatten)]
+    strategy: strategy::StrategyArgs,
+
+    #[command(flatten)]
+    pub alert: alert::AlertArgs,
 }
 
 impl Command {
     pub async fn run(&self) -> anyhow::Result<()> {
-        let agent = self.identity.create_agent().await?;
+        let notifier = self.alert.notifier()?;
+        // The gauge is only set when a run succeeds, so start it from the journal, for the
+        // textfile of a run which fails.
+        match Journal::last(&self.journal) {
+            Ok(run) => {
+                if let Some(at) = run.and_then(|r| r.last_completed_at()) {
+                    metrics::record_success(at);
+                }
+            }
+            Err(err) => {
+                warn!(error = %format!("{:#}", err), "Couldn't read the last successful run")
+            }
+        }
+        let result = self.run_job(&notifier).await;
+        // Dry runs change nothing, so there's nothing to alert on if one fails.
+        match &result {
+            Err(err) if !self.dry_run => {
+                let run = Journal::interrupted(&self.journal).ok().flatten();
+                notifier.run_failed(err, run.as_ref()).await;
+            }
+            _ => {}
+        }
+        // Written whether or not the run succeeded, so failures show up too. Failing to write it
+        // mustn't hide how the run itself went.
+        if let Some(path) = &self.metrics_textfile {
+            if let Err(err) = metrics::write_textfile(path) {
+                warn!(error = %format!("{:#}", err), "Couldn't write metrics textfile");
+            }
+        }
+        result
+    }
+
+    async fn run_job(&self, notifier: &alert::Notifier) -> anyhow::Result<()> {
         let local_agent = self.identity.create_local_agent().await?;
 
         let deposits_canister_id = Principal::from_text(&self.identity.deposits_canister)?;
         let d = deposits::Agent {
             agent: &local_agent,
             canister_id: deposits_canister_id,
+            retry: self.identity.retry.policy(),
         };
         let deposits_address = d.account_id()?;
 
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+
+        let agent = self.identity.create_agent().await?;
         let governance_canister_id = Principal::from_text(&self.identity.governance)?;
         let g = governance::Agent {
             agent: &agent,
             canister_id: governance_canister_id,
+            retry: self.identity.retry.policy(),
+            approvals: self.identity.approval_policy()?,
+            disburse_safety: self.identity.disburse_safety()?,
         };
+        let strategy = self.strategy.strategy();
 
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)?
-            .as_secs();
+        if self.dry_run {
+            let interrupted = Journal::interrupted(&self.journal)?;
+            return plan(
+                &d,
+                &g,
+                &deposits_address,
+                interrupted.as_ref(),
+                now,
+                &strategy,
+            )
+            .await;
+        }
 
-        // Disburse any pending neurons
-        eprintln!("Disbursing any pending neurons");
-        let neurons_to_disburse = d.list_neurons_to_disburse(now).await?;
-        eprintln!("Found {} neurons to disburse", neurons_to_disburse.len());
-        g.disburse_neurons(&deposits_address, &neurons_to_disburse).await?;
+        if let Err(err) = notifier.check_stale().await {
+            warn!(error = %format!("{:#}", err), "Couldn't check when the last run was");
+        }
 
-        // Run canister updates and figure out which neurons to split
-        eprintln!("Refreshing staking neurons and applying interest");
-        let neurons_to_split = d.refresh_neurons_and_apply_interest().await?;
+        let mut journal = Journal::open(&self.journal, now)?;
+        if journal.resumed {
+            info!(
+                started_at = journal.run.started_at,
+                journal = %journal.path().display(),
+                "Resuming interrupted run"
+            );
+        }
 
-        eprintln!("Splitting {} neurons", neurons_to_split.len());
-        let neurons_to_replace = g.split_new_withdrawal_neurons(neurons_to_split).await?;
+        execute(
+            &d,
+            &g,
+            &deposits_address,
+            &mut journal,
+            now,
+            self.disburse_reads,
+            &strategy,
+        )
+        .await?;
 
-        for (id, new_id) in neurons_to_replace.iter() {
-            d.replace_staking_neuron(id.clone(), new_id.clone()).await?;
-            eprintln!("Replaced neuron {} with new neuron {}", id, new_id);
+        // The run itself succeeded, so failing to look for anomalies only gets a warning.
+        if let Err(err) = notifier.check_run(&d, &journal.run).await {
+            warn!(error = %format!("{:#}", err), "Couldn't check the run for anomalies");
         }
-
         Ok(())
     }
 }
+
+// Run (or finish) the daily job, recording each step in the journal before moving on to the next.
+// Returns the review of the splits, if this run made it rather than an interrupted one.
+pub async fn execute<D, G>(
+    d: &D,
+    g: &G,
+    deposits_address: &AccountIdentifier,
+    journal: &mut Journal,
+    now: u64,
+    disburse_reads: ReadMode,
+    strategy: &Strategy,
+) -> anyhow::Result<Option<Review>>
+where
+    D: DepositsService + Sync,
+    G: GovernanceService + Sync,
+{
+    // Disburse any pending neurons
+    if !journal.run.disburse_complete {
+        async {
+            info!("Disbursing any pending neurons");
+            let timer = metrics::STEP_DURATION
+                .with_label_values(&["disburse"])
+                .start_timer();
+            let neurons_to_disburse: Vec<u64> = d
+                .list_neurons_to_disburse(now, disburse_reads)
+                .await?
+                .into_iter()
+                .filter(|id| !journal.run.disbursed.contains(id))
+                .collect();
+            info!(count = neurons_to_disburse.len(), "Found neurons to disburse");
+            for id in neurons_to_disburse.iter() {
+                g.disburse_neurons(deposits_address, &[*id]).await?;
+                journal.record_disbursed(*id)?;
+                metrics::NEURONS.with_label_values(&["disbursed"]).inc();
+            }
+            journal.record_disburse_complete()?;
+            timer.observe_duration();
+            Ok::<_, anyhow::Error>(())
+        }
+        .instrument(info_span!("disburse"))
+        .await?;
+    }
+
+    // Run canister updates and figure out which neurons to split
+    if journal.run.splits.is_none() {
+        async {
+            info!("Refreshing staking neurons and applying interest");
+            let timer = metrics::STEP_DURATION
+                .with_label_values(&["refresh"])
+                .start_timer();
+            let neurons_to_split = d.refresh_neurons_and_apply_interest().await?;
+            journal.record_splits(&neurons_to_split)?;
+            timer.observe_duration();
+            Ok::<_, anyhow::Error>(())
+        }
+        .instrument(info_span!("refresh"))
+        .await?;
+    }
+
+    // Check the canister's splits, and compare them with our own, before any are sent
+    let mut reviewed = None;
+    if !journal.run.splits_reviewed {
+        reviewed = async {
+            info!("Reviewing splits");
+            let timer = metrics::STEP_DURATION
+                .with_label_values(&["review"])
+                .start_timer();
+            let neurons_to_split: Vec<(u64, u64, bool)> = journal
+                .run
+                .splits
+                .iter()
+                .flatten()
+                .map(|s| (s.id, s.amount_e8s, s.should_replace))
+                .collect();
+            let candidates = strategy::candidates(d, g, now).await?;
+            let review = strategy.review(&neurons_to_split, &candidates);
+            review.report();
+            strategy.check(&review)?;
+            journal.record_splits_reviewed()?;
+            timer.observe_duration();
+            Ok::<_, anyhow::Error>(Some(review))
+        }
+        .instrument(info_span!("review"))
+        .await?;
+    }
+
+    let splits = journal.run.splits.clone().unwrap_or_default();
+    info!(count = splits.len(), "Splitting neurons");
+    for (index, mut split) in splits.into_iter().enumerate() {
+        let span = info_span!(
+            "split",
+            neuron_id = split.id,
+            amount_e8s = split.amount_e8s,
+            should_replace = split.should_replace
+        );
+        async {
+            while !split.is_complete() {
+                split.step = match split.step {
+                    SplitStep::Planned => {
+                        info!("Splitting neuron");
+                        // Wait for any approval before recording the request, so a split which is
+                        // refused for lack of approval can simply be retried on the next run.
+                        g.check_approval(&Operation::Split {
+                            neuron_id: split.id,
+                            amount_e8s: split.amount_e8s,
+                        })?;
+                        journal.record_split_step(index, SplitStep::Requested)?;
+                        let timer = metrics::STEP_DURATION
+                            .with_label_values(&["split"])
+                            .start_timer();
+                        let new_id = g.split_neuron(split.id, split.amount_e8s).await?;
+                        timer.observe_duration();
+                        metrics::NEURONS.with_label_values(&["split"]).inc();
+                        metrics::SPLIT_E8S.inc_by(split.amount_e8s);
+                        info!(new_id, "Created new neuron");
+                        SplitStep::Split { new_id }
+                    }
+                    SplitStep::Requested => {
+                        let claimed: Vec<u64> = journal
+                            .run
+                            .splits
+                            .iter()
+                            .flatten()
+                            .filter_map(|s| s.new_id())
+                            .collect();
+                        match split_child(g, &split, journal.run.started_at, &claimed).await? {
+                            Some(new_id) => {
+                                info!(new_id, "Found the neuron an interrupted split created");
+                                SplitStep::Split { new_id }
+                            }
+                            None => {
+                                info!("Interrupted split never happened, splitting again");
+                                SplitStep::Planned
+                            }
+                        }
+                    }
+                    SplitStep::Split { new_id } => {
+                        // When replacing, the old neuron becomes the withdrawal neuron, otherwise
+                        // the new one does.
+                        let dissolving_id = if split.should_replace { split.id } else { new_id };
+                        let timer = metrics::STEP_DURATION
+                            .with_label_values(&["dissolve"])
+                            .start_timer();
+                        g.start_dissolving(dissolving_id).await?;
+                        timer.observe_duration();
+                        info!(dissolving_id, "Started dissolving neuron");
+                        SplitStep::Dissolving { new_id }
+                    }
+                    SplitStep::Dissolving { new_id } => {
+                        let timer = metrics::STEP_DURATION
+                            .with_label_values(&["replace"])
+                            .start_timer();
+                        d.replace_staking_neuron(split.id, new_id).await?;
+                        timer.observe_duration();
+                        metrics::NEURONS.with_label_values(&["replaced"]).inc();
+                        info!(new_id, "Replaced staking neuron with new neuron");
+                        SplitStep::Replaced { new_id }
+                    }
+                    SplitStep::Replaced { .. } => break,
+                };
+                journal.record_split_step(index, split.step)?;
+            }
+            Ok::<_, anyhow::Error>(())
+        }
+        .instrument(span)
+        .await?;
+    }
+
+    journal.record_complete(now)?;
+    metrics::record_success(now);
+    Ok(reviewed)
+}
+
+// Find the neuron a split whose response was lost created, if it created one. A split's new neuron
+// has the parent's dissolve state, the split amount less the fee as its stake, and was created
+// since the run started. `claimed` are neurons other splits in the run already created.
+async fn split_child<G>(
+    g: &G,
+    split: &Split,
+    started_at: u64,
+    claimed: &[u64],
+) -> anyhow::Result<Option<u64>>
+where
+    G: GovernanceService + Sync,
+{
+    let neurons = g.list_neurons(vec![], ReadMode::Certified).await?;
+    let Some(parent) = neurons
+        .iter()
+        .find(|n| n.id.as_ref().map_or(false, |n| n.id == split.id))
+    else {
+        bail!("Neuron {} to split no longer exists", split.id);
+    };
+    let children: Vec<u64> = neurons
+        .iter()
+        .filter(|n| {
+            n.dissolve_state == parent.dissolve_state
+                && n.cached_neuron_stake_e8s == split.amount_e8s.saturating_sub(governance::ICP_FEE)
+                && n.created_timestamp_seconds >= started_at
+        })
+        .filter_map(|n| n.id.as_ref().map(|n| n.id))
+        .filter(|id| *id != split.id && !claimed.contains(id))
+        .collect();
+    match children[..] {
+        [] => Ok(None),
+        [new_id] => Ok(Some(new_id)),
+        _ => bail!(
+            "Neurons {:?} could each have been split off neuron {}. Check governance, then record \
+             the result in the journal",
+            children,
+            split.id
+        ),
+    }
+}
+
+// Print the steps the daily job would take, without issuing any update calls to the deposits or
+// governance canisters. If a previous run was interrupted, only the steps remaining from that run
+// are shown.
+pub async fn plan<D, G>(
+    d: &D,
+    g: &G,
+    deposits_address: &AccountIdentifier,
+    interrupted: Option<&Run>,
+    now: u64,
+    strategy: &Strategy,
+) -> anyhow::Result<()>
+where
+    D: DepositsService + Sync,
+    G: GovernanceService + Sync,
+{
+    if let Some(run) = interrupted {
+        println!("Would resume interrupted run started at {}", run.started_at);
+    }
+
+    if interrupted.map_or(false, |r| r.disburse_complete) {
+        println!("Disburse: already complete");
+    } else {
+        let disbursed = interrupted.map(|r| r.disbursed.clone()).unwrap_or_default();
+        let neurons_to_disburse: Vec<u64> = d
+            .list_neurons_to_disburse(now, ReadMode::Query)
+            .await?
+            .into_iter()
+            .filter(|id| !disbursed.contains(id))
+            .collect();
+        println!("Disburse: {} neurons", neurons_to_disburse.len());
+        for id in neurons_to_disburse.iter() {
+            println!("  disburse neuron {} to {}", id, deposits_address);
+        }
+    }
+
+    let splits = match interrupted.and_then(|r| r.splits.clone()) {
+        Some(splits) => {
+            println!("Refresh: already complete");
+            splits
+        }
+        None => {
+            println!("Refresh: would call refreshNeuronsAndApplyInterest, current plan is:");
+            d.preview_neurons_to_split()
+                .await?
+                .into_iter()
+                .map(|(id, amount_e8s, should_replace)| Split {
+                    id,
+                    amount_e8s,
+                    should_replace,
+                    step: SplitStep::Planned,
+                })
+                .collect()
+        }
+    };
+
+    if interrupted.map_or(false, |r| r.splits.is_some() && r.splits_reviewed) {
+        println!("Review: already complete");
+    } else {
+        let neurons_to_split: Vec<(u64, u64, bool)> = splits
+            .iter()
+            .map(|s| (s.id, s.amount_e8s, s.should_replace))
+            .collect();
+        let candidates = strategy::candidates(d, g, now).await?;
+        let review = strategy.review(&neurons_to_split, &candidates);
+        println!(
+            "Review: {} differences from the oracle's plan",
+            review.divergences.len()
+        );
+        for divergence in review.divergences.iter() {
+            println!("  {}", divergence);
+        }
+        if let Err(err) = strategy.check(&review) {
+            println!("  run would stop here: {}", err);
+        }
+    }
+
+    println!("Split: {} neurons", splits.len());
+    for split in splits.iter().filter(|s| !s.is_complete()) {
+        let new_neuron = match split.step {
+            SplitStep::Planned => {
+                println!("  split {} e8s off neuron {}", split.amount_e8s, split.id);
+                "<new>".to_string()
+            }
+            SplitStep::Requested => {
+                println!(
+                    "  split of neuron {} has an unknown outcome, run would look for its new \
+                     neuron in governance, or split again",
+                    split.id
+                );
+                "<new>".to_string()
+            }
+            SplitStep::Split { new_id }
+            | SplitStep::Dissolving { new_id }
+            | SplitStep::Replaced { new_id } => new_id.to_string(),
+        };
+        if matches!(
+            split.step,
+            SplitStep::Planned | SplitStep::Requested | SplitStep::Split { .. }
+        ) {
+            if split.should_replace {
+                println!("  start dissolving neuron {}", split.id);
+            } else {
+                println!("  start dissolving neuron {}", new_neuron);
+            }
+        }
+        if split.should_replace {
+            println!("  replace staking neuron {} with {}", split.id, new_neuron);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use anyhow::{bail, Context};
+use clap::Args;
+use std::{fs::OpenOptions, io::Write, os::unix::fs::OpenOptionsExt, path::PathBuf};
+
+use crate::identity::{self, keystore};
+
+#[derive(Args, Debug)]
+pub struct Command {
+    /// PEM Key file to encrypt, or - to read it from STDIN
+    #[arg(long)]
+    private_pem: PathBuf,
+
+    /// Keystore file to write. Must not already exist
+    #[arg(long)]
+    keystore: PathBuf,
+
+    /// Environment variable to read the keystore passphrase from
+    #[arg(long, default_value = "ORACLE_KEYSTORE_PASSPHRASE")]
+    passphrase_env: String,
+
+    /// File descriptor to read the keystore passphrase from, instead of an environment variable
+    #[arg(long)]
+    passphrase_fd: Option<i32>,
+}
+
+impl Command {
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let pem = identity::read_file(&self.private_pem, "PEM")?;
+        // Make sure it is a key we can actually use before encrypting it.
+        identity::identity_from_pem(&pem)?;
+
+        let passphrase = keystore::read_passphrase(&self.passphrase_env, self.passphrase_fd)?;
+        let contents = keystore::encrypt(&pem, &passphrase)?;
+
+        if self.keystore.exists() {
+            bail!("{} already exists", self.keystore.display());
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&self.keystore)
+            .with_context(|| format!("Couldn't create {}", self.keystore.display()))?;
+        file.write_all(contents.as_bytes())
+            .with_context(|| format!("Couldn't write {}", self.keystore.display()))?;
+
+        println!("Wrote keystore to {}", self.keystore.display());
+        Ok(())
+    }
+}
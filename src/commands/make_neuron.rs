@@ -1,31 +1,51 @@
-use anyhow::{anyhow, bail};
+use anyhow::{anyhow, bail, Context};
 use candid::Principal;
 use clap::Args;
 use icp_ledger::{AccountIdentifier, Subaccount};
 use k256::sha2::{Digest, Sha256};
 use rand::Rng;
-use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf, time::SystemTime};
+use tracing::info;
 
 use crate::deposits::{self, Service as DepositsService};
+use crate::file;
 use crate::governance::{self, Service as GovernanceService};
 use crate::identity;
-use crate::ledger::{self, Service as LedgerService};
-
-const DEFAULT_ICP_LEDGER_CANISTER_ID: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+use crate::ledger::{
+    self,
+    icrc::{self, Service as IcrcService},
+    Service as LedgerService,
+};
 
 #[derive(Args, Debug)]
 pub struct Command {
     #[command(flatten)]
     identity: identity::IdentityArgs,
 
-    /// Principal of the deposits canister
-    #[arg(long, default_value = DEFAULT_ICP_LEDGER_CANISTER_ID)]
-    icp_ledger: String,
+    #[command(flatten)]
+    ledger: ledger::LedgerArgs,
 
     /// Memo to use when creating the neuron, if 0, use random number
     #[arg(long, default_value = "0")]
     memo: u64,
 
+    /// Timestamp (nanoseconds since the unix epoch) to send the stake transfer with. Rerunning
+    /// with the same memo and timestamp within 24 hours will not transfer the stake twice.
+    /// Defaults to now
+    #[arg(long)]
+    created_at_time: Option<u64>,
+
+    /// File to record the memo and timestamp of the stake transfer in before sending it. An
+    /// interrupted run is resumed from here, so the ledger sees the same transfer again and
+    /// doesn't send the stake twice. Removed once the neuron is made
+    #[arg(
+        long,
+        env = "ORACLE_MAKE_NEURON_INTENT",
+        default_value = "make-neuron-intent.json"
+    )]
+    intent: PathBuf,
+
     /// Delay to set on the neuron,
     #[arg(long, default_value = "0")]
     delay: u32,
@@ -42,61 +62,144 @@ impl Command {
         let g = governance::Agent {
             agent: &agent,
             canister_id: governance_principal,
+            retry: self.identity.retry.policy(),
+            approvals: self.identity.approval_policy()?,
+            disburse_safety: self.identity.disburse_safety()?,
         };
 
         let identity_principal = self.identity.principal().await?;
 
-        let icp = ledger::Agent {
-            agent: &local_agent,
-            canister_id: Principal::from_text(&self.icp_ledger)?,
-        };
+        let icp_ledger = self.ledger.icp_ledger()?;
 
-        let memo = if self.memo == 0 {
-            // Pick a random memo
-            rand::thread_rng().gen()
-        } else {
-            self.memo
-        };
+        let intent = self.intent()?;
+        let memo = intent.memo;
 
-        let address = neuron_account_id(governance_principal, identity_principal, memo)?;
+        let subaccount = neuron_subaccount(identity_principal, memo)?;
+        let address = AccountIdentifier::new(governance_principal.into(), Some(subaccount));
+
+        let mut transfer = ledger::TransferRequest::new(address, 100_000_000, memo)?;
+        transfer.created_at_time = intent.created_at_time;
 
         // Transfer 1 ICP
-        eprintln!("Transfer 1 ICP to {}, memo: {}", address.to_hex(), memo);
-        let height = icp.transfer(address, 100_000_000, memo).await?;
-        eprintln!("Transferred at block height: {}", height);
+        info!(
+            to = %address.to_hex(),
+            memo,
+            created_at_time = transfer.created_at_time,
+            "Transfer 1 ICP. If this fails, rerun with the same --intent to retry without sending \
+             twice"
+        );
+        let height = match self.ledger.icp_ledger_interface {
+            ledger::Interface::Legacy => {
+                let icp = ledger::Agent {
+                    agent: &local_agent,
+                    canister_id: icp_ledger,
+                    retry: self.identity.retry.policy(),
+                };
+                icp.transfer(&transfer).await?
+            }
+            ledger::Interface::Icrc1 => {
+                let icp = icrc::Agent {
+                    agent: &local_agent,
+                    canister_id: icp_ledger,
+                    retry: self.identity.retry.policy(),
+                };
+                icp.transfer(&icrc::TransferRequest {
+                    from_subaccount: None,
+                    to: icrc::Account {
+                        owner: governance_principal,
+                        subaccount: Some(subaccount.0.to_vec()),
+                    },
+                    amount_e8s: transfer.amount_e8s,
+                    memo,
+                    created_at_time: transfer.created_at_time,
+                })
+                .await?
+            }
+        };
+        info!(height, "Transferred");
 
         // Create the Neuron
         let neuron_id = g.claim_neuron(Some(identity_principal), memo).await?;
-        eprintln!("Created neuron: {}", neuron_id);
+        info!(neuron_id, "Created neuron");
 
-        eprintln!("Add hot key to neuron: {}", deposits_principal);
+        info!(neuron_id, hotkey = %deposits_principal, "Add hot key to neuron");
         g.add_hotkey(neuron_id, deposits_principal).await?;
 
         if self.delay > 0 {
-            eprintln!("Set the neuron delay: {}", self.delay);
+            info!(neuron_id, delay = self.delay, "Set the neuron delay");
             g.increase_neuron_delay(neuron_id, self.delay).await?;
         }
 
-        eprintln!("Enabling auto-merge-maturity");
+        info!(neuron_id, "Enabling auto-merge-maturity");
         g.enable_auto_merge_maturity(neuron_id).await?;
 
+        fs::remove_file(&self.intent)
+            .with_context(|| format!("Couldn't remove {}", self.intent.display()))?;
+
         println!("{}", neuron_id);
         Ok(())
     }
+
+    // The stake transfer to send: the one an interrupted run recorded, or a new one, recorded
+    // before anything is sent.
+    fn intent(&self) -> anyhow::Result<Intent> {
+        if self.intent.exists() {
+            let intent: Intent = serde_json::from_slice(&fs::read(&self.intent)?)
+                .with_context(|| format!("Couldn't read {}", self.intent.display()))?;
+            if (self.memo != 0 && self.memo != intent.memo)
+                || self
+                    .created_at_time
+                    .map_or(false, |t| t != intent.created_at_time)
+            {
+                bail!(
+                    "{} is for memo {} at {}, from an interrupted run. Finish that one first, or \
+                     remove the file if its stake was never sent",
+                    self.intent.display(),
+                    intent.memo,
+                    intent.created_at_time
+                );
+            }
+            info!(
+                memo = intent.memo,
+                created_at_time = intent.created_at_time,
+                "Resuming an interrupted run"
+            );
+            return Ok(intent);
+        }
+
+        let intent = Intent {
+            memo: if self.memo == 0 {
+                // Pick a random memo
+                rand::thread_rng().gen()
+            } else {
+                self.memo
+            },
+            created_at_time: match self.created_at_time {
+                Some(created_at_time) => created_at_time,
+                None => SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)?
+                    .as_nanos() as u64,
+            },
+        };
+        file::write_atomic(&self.intent, &serde_json::to_vec_pretty(&intent)?)
+            .with_context(|| format!("Couldn't write {}", self.intent.display()))?;
+        Ok(intent)
+    }
 }
 
-fn neuron_account_id(
-    governance: Principal,
-    controller: Principal,
-    nonce: u64,
-) -> anyhow::Result<AccountIdentifier> {
+#[derive(Serialize, Deserialize, Debug)]
+struct Intent {
+    memo: u64,
+    created_at_time: u64,
+}
+
+// The subaccount of the governance canister which stakes a neuron for the controller and nonce.
+fn neuron_subaccount(controller: Principal, nonce: u64) -> anyhow::Result<Subaccount> {
     let mut hasher = Sha256::new();
     hasher.update(vec![
         0x0c, 0x6e, 0x65, 0x75, 0x72, 0x6f, 0x6e, 0x2d, 0x73, 0x74, 0x61, 0x6b, 0x65,
     ]);
     hasher.update(controller.as_slice());
     hasher.update(nonce.to_be_bytes());
-    let subaccount: Subaccount = hasher.finalize().as_slice().try_into()?;
-
-    Ok(AccountIdentifier::new(governance.into(), Some(subaccount)))
+    Ok(hasher.finalize().as_slice().try_into()?)
 }
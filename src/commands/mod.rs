@@ -1,13 +1,40 @@
 use clap::Subcommand;
 
-mod daily;
+mod approve;
+mod check;
+mod config;
+pub mod daily;
+mod encrypt_key;
 mod make_neuron;
+mod neurons;
+mod reconcile;
+mod serve;
+mod sticp;
+mod test_alert;
 
 #[derive(Subcommand, Debug)]
 pub enum Command {
+    /// Add an operator's approval to an approval request
+    Approve(approve::Command),
+    /// Check the configuration, signer and canisters are all working, without changing anything
+    Check(check::Command),
+    /// Show the settings the oracle would run with
+    Config(config::Command),
     /// Triggers the daily job to: apply interest, flush pending deposits, split new withdrawal
     /// neurons.
     Daily(daily::Command),
+    /// Encrypt a PEM key into a keystore file, for use with --keystore
+    EncryptKey(encrypt_key::Command),
     /// Make a new neuron owned by the signing canister
     MakeNeuron(make_neuron::Command),
+    /// List the neurons readable by the signer, with their stake, maturity and dissolve state
+    Neurons(neurons::Command),
+    /// Compare the deposits canister's cached neuron state with governance
+    Reconcile(reconcile::Command),
+    /// Stay resident, running the daily job on a schedule
+    Serve(serve::Command),
+    /// Check balances and approve or move stICP on its ICRC ledger
+    Sticp(sticp::Command),
+    /// Send a test alert to every configured alert sink
+    TestAlert(test_alert::Command),
 }
@@ -0,0 +1,160 @@
+use candid::Principal;
+use clap::{Args, ValueEnum};
+use ic_nns_governance::pb::v1::{neuron::DissolveState, Neuron};
+use serde::Serialize;
+use std::time::SystemTime;
+
+use crate::governance::{self, Service as GovernanceService};
+use crate::identity;
+use crate::query::ReadMode;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(Args, Debug)]
+pub struct Command {
+    #[command(flatten)]
+    identity: identity::IdentityArgs,
+
+    /// Output format
+    #[arg(long, value_enum, default_value = "table")]
+    format: Format,
+
+    /// How to read the neurons from governance
+    #[arg(long, value_enum, default_value = "query")]
+    reads: ReadMode,
+}
+
+// One neuron's status, as reported.
+#[derive(Serialize, Debug)]
+struct Row {
+    id: u64,
+    stake_e8s: u64,
+    staked_maturity_e8s: u64,
+    maturity_e8s: u64,
+    dissolve_state: &'static str,
+    dissolve_delay_seconds: u64,
+    age_seconds: u64,
+    auto_stake_maturity: bool,
+    hot_keys: Vec<String>,
+}
+
+impl Row {
+    fn new(neuron: &Neuron, now: u64) -> Self {
+        let (dissolve_state, dissolve_delay_seconds) = match neuron.dissolve_state {
+            Some(DissolveState::WhenDissolvedTimestampSeconds(ts)) if ts > now => {
+                ("dissolving", ts - now)
+            }
+            Some(DissolveState::WhenDissolvedTimestampSeconds(_)) => ("dissolved", 0),
+            Some(DissolveState::DissolveDelaySeconds(0)) | None => ("dissolved", 0),
+            Some(DissolveState::DissolveDelaySeconds(delay)) => ("locked", delay),
+        };
+        // Dissolving neurons don't age, and have their aging timestamp set to u64::MAX.
+        let age_seconds = now.saturating_sub(neuron.aging_since_timestamp_seconds);
+        Self {
+            id: neuron.id.as_ref().map_or(0, |n| n.id),
+            stake_e8s: neuron
+                .cached_neuron_stake_e8s
+                .saturating_sub(neuron.neuron_fees_e8s),
+            staked_maturity_e8s: neuron.staked_maturity_e8s_equivalent.unwrap_or(0),
+            maturity_e8s: neuron.maturity_e8s_equivalent,
+            dissolve_state,
+            dissolve_delay_seconds,
+            age_seconds,
+            auto_stake_maturity: neuron.auto_stake_maturity.unwrap_or(false),
+            hot_keys: neuron.hot_keys.iter().map(|k| k.to_string()).collect(),
+        }
+    }
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.stake_e8s.to_string(),
+            self.staked_maturity_e8s.to_string(),
+            self.maturity_e8s.to_string(),
+            self.dissolve_state.to_string(),
+            self.dissolve_delay_seconds.to_string(),
+            self.age_seconds.to_string(),
+            self.auto_stake_maturity.to_string(),
+            self.hot_keys.join(" "),
+        ]
+    }
+}
+
+const HEADERS: [&str; 9] = [
+    "id",
+    "stake_e8s",
+    "staked_maturity_e8s",
+    "maturity_e8s",
+    "dissolve_state",
+    "dissolve_delay_seconds",
+    "age_seconds",
+    "auto_stake_maturity",
+    "hot_keys",
+];
+
+impl Command {
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let agent = self.identity.create_agent().await?;
+        let g = governance::Agent {
+            agent: &agent,
+            canister_id: Principal::from_text(&self.identity.governance)?,
+            retry: self.identity.retry.policy(),
+            approvals: self.identity.approval_policy()?,
+            disburse_safety: self.identity.disburse_safety()?,
+        };
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        let mut rows: Vec<Row> = g
+            .list_neurons(vec![], self.reads)
+            .await?
+            .iter()
+            .map(|n| Row::new(n, now))
+            .collect();
+        rows.sort_by_key(|r| r.id);
+
+        match self.format {
+            Format::Json => println!("{}", serde_json::to_string_pretty(&rows)?),
+            Format::Csv => {
+                println!("{}", HEADERS.join(","));
+                for row in rows.iter() {
+                    println!("{}", row.fields().join(","));
+                }
+            }
+            Format::Table => {
+                let fields: Vec<Vec<String>> = rows.iter().map(|r| r.fields()).collect();
+                let widths: Vec<usize> = HEADERS
+                    .iter()
+                    .enumerate()
+                    .map(|(i, h)| {
+                        fields
+                            .iter()
+                            .map(|f| f[i].len())
+                            .chain([h.len()])
+                            .max()
+                            .unwrap_or(0)
+                    })
+                    .collect();
+                let print_row = |cells: Vec<&str>| {
+                    let line: Vec<String> = cells
+                        .iter()
+                        .zip(widths.iter())
+                        .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                        .collect();
+                    println!("{}", line.join("  ").trim_end());
+                };
+                print_row(HEADERS.to_vec());
+                for row in fields.iter() {
+                    print_row(row.iter().map(|s| s.as_str()).collect());
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::bail;
+use candid::Principal;
+use clap::Args;
+use ic_base_types::PrincipalId;
+use ic_nns_governance::pb::v1::Neuron;
+use std::fmt;
+use tracing::info;
+
+use crate::deposits::{self, Service as DepositsService};
+use crate::governance::{self, Service as GovernanceService};
+use crate::identity;
+use crate::query::ReadMode;
+
+#[derive(Args, Debug)]
+pub struct Command {
+    #[command(flatten)]
+    identity: identity::IdentityArgs,
+
+    /// Have the deposits canister refresh any neurons whose cached stake or dissolve state is
+    /// wrong, then check again
+    #[arg(long)]
+    fix: bool,
+
+    /// How to read both sides. Certified reads go through consensus, so can't be faked by a single
+    /// replica, but are slower
+    #[arg(long, value_enum, default_value = "query")]
+    reads: ReadMode,
+}
+
+// A difference between what the deposits canister has cached and what governance says.
+#[derive(Debug, PartialEq)]
+enum Discrepancy {
+    // The deposits canister tracks a neuron governance doesn't know of (or the signer can't read).
+    Missing {
+        id: u64,
+    },
+    // A neuron the deposits canister is a hotkey of, but doesn't track. Other neurons the signer
+    // can read, e.g. its own, aren't the canister's to track.
+    Untracked {
+        id: u64,
+    },
+    Stake {
+        id: u64,
+        cached: u64,
+        actual: u64,
+    },
+    DissolveState {
+        id: u64,
+        cached: String,
+        actual: String,
+    },
+    // The deposits canister can no longer manage the neuron.
+    NotHotkey {
+        id: u64,
+    },
+}
+
+impl Discrepancy {
+    // Whether the deposits canister can correct this itself by re-reading the neuron.
+    fn fixable(&self) -> Option<u64> {
+        match self {
+            Discrepancy::Stake { id, .. } | Discrepancy::DissolveState { id, .. } => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Discrepancy::Missing { id } => write!(f, "neuron {}: missing from governance", id),
+            Discrepancy::Untracked { id } => {
+                write!(f, "neuron {}: not tracked by the deposits canister", id)
+            }
+            Discrepancy::Stake { id, cached, actual } => write!(
+                f,
+                "neuron {}: cached stake {} e8s, governance has {} e8s",
+                id, cached, actual
+            ),
+            Discrepancy::DissolveState { id, cached, actual } => write!(
+                f,
+                "neuron {}: cached dissolve state {}, governance has {}",
+                id, cached, actual
+            ),
+            Discrepancy::NotHotkey { id } => {
+                write!(f, "neuron {}: deposits canister is not a hotkey", id)
+            }
+        }
+    }
+}
+
+impl Command {
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let local_agent = self.identity.create_local_agent().await?;
+        let deposits_canister_id = Principal::from_text(&self.identity.deposits_canister)?;
+        let d = deposits::Agent {
+            agent: &local_agent,
+            canister_id: deposits_canister_id,
+            retry: self.identity.retry.policy(),
+        };
+
+        let agent = self.identity.create_agent().await?;
+        let g = governance::Agent {
+            agent: &agent,
+            canister_id: Principal::from_text(&self.identity.governance)?,
+            retry: self.identity.retry.policy(),
+            approvals: self.identity.approval_policy()?,
+            disburse_safety: self.identity.disburse_safety()?,
+        };
+
+        let mut discrepancies = diff(&d, &g, deposits_canister_id, self.reads).await?;
+        for discrepancy in discrepancies.iter() {
+            println!("{}", discrepancy);
+        }
+
+        let fixable: Vec<u64> = discrepancies.iter().filter_map(|d| d.fixable()).collect();
+        if self.fix && !fixable.is_empty() {
+            info!(count = fixable.len(), "Refreshing neurons in the deposits canister");
+            d.refresh_neurons(fixable).await?;
+            discrepancies = diff(&d, &g, deposits_canister_id, self.reads).await?;
+            println!("After refreshing:");
+            for discrepancy in discrepancies.iter() {
+                println!("{}", discrepancy);
+            }
+        }
+
+        if !discrepancies.is_empty() {
+            bail!("{} discrepancies found", discrepancies.len());
+        }
+        println!("Deposits canister agrees with governance");
+        Ok(())
+    }
+}
+
+// Compare the deposits canister's view of its neurons with governance, neuron by neuron.
+async fn diff<D, G>(
+    d: &D,
+    g: &G,
+    deposits_canister_id: Principal,
+    mode: ReadMode,
+) -> anyhow::Result<Vec<Discrepancy>>
+where
+    D: DepositsService + Sync,
+    G: GovernanceService + Sync,
+{
+    let staking = d.list_staking_neurons(mode).await?;
+    let withdrawal = d.list_withdrawal_neurons(mode).await?;
+    let tracked: Vec<u64> = staking
+        .iter()
+        .copied()
+        .chain(withdrawal.iter().map(|n| n.id))
+        .collect();
+    let actual = g.list_neurons(tracked.clone(), mode).await?;
+    let find = |id: u64| -> Option<&Neuron> {
+        actual
+            .iter()
+            .find(|n| n.id.as_ref().map(|n| n.id) == Some(id))
+    };
+
+    let hotkey = PrincipalId(deposits_canister_id);
+    let mut discrepancies = vec![];
+    for id in tracked.iter() {
+        let Some(neuron) = find(*id) else {
+            discrepancies.push(Discrepancy::Missing { id: *id });
+            continue;
+        };
+        if !neuron.hot_keys.contains(&hotkey) {
+            discrepancies.push(Discrepancy::NotHotkey { id: *id });
+        }
+    }
+
+    // Only withdrawal neurons come with cached state to compare.
+    for cached in withdrawal.iter() {
+        let Some(neuron) = find(cached.id) else {
+            continue;
+        };
+        if cached.cached_neuron_stake_e8s != neuron.cached_neuron_stake_e8s {
+            discrepancies.push(Discrepancy::Stake {
+                id: cached.id,
+                cached: cached.cached_neuron_stake_e8s,
+                actual: neuron.cached_neuron_stake_e8s,
+            });
+        }
+        if cached.dissolve_state != neuron.dissolve_state {
+            discrepancies.push(Discrepancy::DissolveState {
+                id: cached.id,
+                cached: format!("{:?}", cached.dissolve_state),
+                actual: format!("{:?}", neuron.dissolve_state),
+            });
+        }
+    }
+
+    for neuron in actual.iter().filter(|n| n.hot_keys.contains(&hotkey)) {
+        let Some(id) = neuron.id.as_ref().map(|n| n.id) else {
+            continue;
+        };
+        if !tracked.contains(&id) {
+            discrepancies.push(Discrepancy::Untracked { id });
+        }
+    }
+    Ok(discrepancies)
+}
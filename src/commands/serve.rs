@@ -0,0 +1,252 @@
+use anyhow::Context;
+use chrono::{DateTime, TimeZone, Utc};
+use clap::Args;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::Infallible,
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
+
+use super::daily;
+use crate::alert;
+use crate::file;
+use crate::journal::Journal;
+use crate::metrics;
+
+// How often to check that runs are still succeeding.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Args, Debug)]
+pub struct Command {
+    #[command(flatten)]
+    daily: daily::Command,
+
+    /// When to run the daily job, as a cron expression with seconds: "sec min hour day month
+    /// weekday", in UTC
+    #[arg(long, env = "ORACLE_SCHEDULE", default_value = "0 0 0 * * *")]
+    schedule: String,
+
+    /// File to record when the job last ran in, so runs missed while the daemon was down are
+    /// caught up on startup
+    #[arg(long, env = "ORACLE_SERVE_STATE", default_value = "oracle-serve.json")]
+    state: PathBuf,
+
+    /// Address to serve the last run's status on, at /status, and Prometheus metrics, at /metrics
+    #[arg(long, env = "ORACLE_LISTEN")]
+    listen: Option<SocketAddr>,
+}
+
+// What the daemon has done so far, persisted across restarts.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Status {
+    // The scheduled time (unix seconds) of the last run, whether or not it succeeded.
+    pub last_scheduled: Option<i64>,
+    pub last_started_at: Option<u64>,
+    pub last_finished_at: Option<u64>,
+    // "ok", or the error the last run failed with.
+    pub last_result: Option<String>,
+    pub running: bool,
+    pub next_run_at: Option<i64>,
+}
+
+impl Status {
+    fn load(path: &PathBuf) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents =
+            fs::read(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("Couldn't parse {}", path.display()))
+    }
+
+    fn save(&self, path: &PathBuf) -> anyhow::Result<()> {
+        file::write_atomic(path, &serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Couldn't write {}", path.display()))
+    }
+}
+
+impl Command {
+    // Stay resident, running the daily job on schedule. Runs happen one at a time in this loop, so
+    // can never overlap.
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let schedule = cron::Schedule::from_str(&self.schedule)
+            .with_context(|| format!("Bad --schedule {}", self.schedule))?;
+        let mut status = Status::load(&self.state)?;
+        // Without a state file, e.g. on the first start, the journal's last run stands in for the
+        // last scheduled one.
+        if status.last_scheduled.is_none() {
+            status.last_scheduled = Journal::last(&self.daily.journal)?
+                .map(|run| run.started_at as i64);
+        }
+        let status = Arc::new(Mutex::new(status));
+        if let Some(addr) = self.listen {
+            tokio::spawn(serve_status(addr, status.clone()));
+        }
+        tokio::spawn(watch_for_missed_runs(self.daily.alert.notifier()?));
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        loop {
+            let last = status.lock().unwrap().last_scheduled;
+            let scheduled = match missed_run(&schedule, last) {
+                Some(missed) => {
+                    info!(scheduled = %missed, "Catching up on missed run");
+                    missed
+                }
+                None => {
+                    let next = self
+                        .wait(&schedule, &status, &mut terminate, &mut interrupt)
+                        .await?;
+                    let Some(next) = next else {
+                        info!("Shutting down");
+                        return Ok(());
+                    };
+                    next
+                }
+            };
+            self.run_once(scheduled, &status).await?;
+        }
+    }
+
+    // Sleep until the next scheduled run, returning its time, or None if asked to shut down.
+    async fn wait(
+        &self,
+        schedule: &cron::Schedule,
+        status: &Mutex<Status>,
+        terminate: &mut tokio::signal::unix::Signal,
+        interrupt: &mut tokio::signal::unix::Signal,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let next = schedule
+            .upcoming(Utc)
+            .next()
+            .context("Schedule has no upcoming runs")?;
+        {
+            let mut status = status.lock().unwrap();
+            status.next_run_at = Some(next.timestamp());
+            self.save(&status);
+        }
+        info!(next_run_at = %next, "Waiting for next run");
+        let delay = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => Ok(Some(next)),
+            _ = terminate.recv() => Ok(None),
+            _ = interrupt.recv() => Ok(None),
+        }
+    }
+
+    async fn run_once(
+        &self,
+        scheduled: DateTime<Utc>,
+        status: &Mutex<Status>,
+    ) -> anyhow::Result<()> {
+        {
+            let mut status = status.lock().unwrap();
+            status.running = true;
+            status.last_started_at = Some(now()?);
+            self.save(&status);
+        }
+
+        // A failed run is reported, and the daemon carries on. The journal lets the next run pick
+        // up where this one left off.
+        let result = self.daily.run().await;
+        if let Err(err) = &result {
+            error!(scheduled = %scheduled, "Daily run failed: {:?}", err);
+        }
+
+        let mut status = status.lock().unwrap();
+        status.running = false;
+        status.last_scheduled = Some(scheduled.timestamp());
+        status.last_finished_at = Some(now()?);
+        status.last_result = Some(match result {
+            Ok(()) => "ok".to_string(),
+            Err(err) => format!("{:#}", err),
+        });
+        self.save(&status);
+        Ok(())
+    }
+
+    // The state file only keeps runs from being missed across restarts, so failing to write it
+    // shouldn't stop the daemon.
+    fn save(&self, status: &Status) {
+        if let Err(err) = status.save(&self.state) {
+            warn!(error = %format!("{:#}", err), "Couldn't save the daemon's state");
+        }
+    }
+}
+
+// Like systemd's Persistent=true: if any run was missed since the last one (e.g. while the daemon
+// was down), the latest missed run is due now. Several missed runs only cause one catch-up run.
+// With no last run at all, the job has never run, so is due now.
+fn missed_run(schedule: &cron::Schedule, last: Option<i64>) -> Option<DateTime<Utc>> {
+    let now = Utc::now();
+    let Some(last) = last else {
+        return Some(now);
+    };
+    let last = Utc.timestamp_opt(last, 0).single()?;
+    schedule.after(&last).take_while(|t| *t <= now).last()
+}
+
+// Alert if runs stop succeeding, even while the daemon is stuck in a run, or waiting for one.
+async fn watch_for_missed_runs(notifier: alert::Notifier) {
+    let mut interval = tokio::time::interval(WATCHDOG_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = notifier.check_stale().await {
+            warn!(error = %format!("{:#}", err), "Couldn't check when the last run was");
+        }
+    }
+}
+
+async fn serve_status(addr: SocketAddr, status: Arc<Mutex<Status>>) {
+    let make_service = make_service_fn(move |_| {
+        let status = status.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let status = status.clone();
+                async move { Ok::<_, Infallible>(respond(request, &status)) }
+            }))
+        }
+    });
+    info!(%addr, "Serving status and metrics");
+    if let Err(err) = Server::bind(&addr).serve(make_service).await {
+        error!(error = %err, "Status server failed");
+    }
+}
+
+fn respond(request: Request<Body>, status: &Mutex<Status>) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/status") => {
+            let body = serde_json::to_vec_pretty(&*status.lock().unwrap()).unwrap_or_default();
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap_or_default()
+        }
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Body::from(metrics::encode()))
+            .unwrap_or_default(),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap_or_default(),
+    }
+}
+
+fn now() -> anyhow::Result<u64> {
+    Ok(SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs())
+}
@@ -0,0 +1,201 @@
+use anyhow::{anyhow, bail};
+use candid::Principal;
+use clap::{Args, Subcommand};
+use std::time::SystemTime;
+use tracing::info;
+
+use crate::identity;
+use crate::ledger::{
+    self,
+    icrc::{self, Icrc2Service, Service as IcrcService},
+};
+use crate::query::ReadMode;
+
+#[derive(Args, Debug)]
+pub struct Command {
+    #[command(flatten)]
+    identity: identity::IdentityArgs,
+
+    #[command(flatten)]
+    ledger: ledger::LedgerArgs,
+
+    #[command(subcommand)]
+    command: SticpCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum SticpCommand {
+    /// Print an account's stICP balance
+    Balance {
+        /// Account, as <principal> or <principal>:<hex subaccount>. Defaults to the oracle's own
+        account: Option<String>,
+
+        /// How to read the balance. Certified reads go through consensus, so can't be faked by a
+        /// single replica, but are slower
+        #[arg(long, value_enum, default_value = "query")]
+        reads: ReadMode,
+    },
+    /// Allow another account to spend the oracle's stICP, with icrc2_approve
+    Approve {
+        /// Account allowed to spend, as <principal> or <principal>:<hex subaccount>
+        #[arg(long)]
+        spender: String,
+
+        /// Allowance to set
+        #[arg(long)]
+        amount_e8s: u64,
+
+        /// Only set the allowance if it is currently this
+        #[arg(long)]
+        expected_allowance_e8s: Option<u64>,
+
+        /// When the allowance expires, in nanoseconds since the unix epoch
+        #[arg(long)]
+        expires_at: Option<u64>,
+
+        #[command(flatten)]
+        dedup: Dedup,
+    },
+    /// Move stICP another account has approved the oracle to spend, with icrc2_transfer_from
+    TransferFrom {
+        /// Account to take the stICP from, as <principal> or <principal>:<hex subaccount>
+        #[arg(long)]
+        from: String,
+
+        /// Account to send the stICP to, as <principal> or <principal>:<hex subaccount>
+        #[arg(long)]
+        to: String,
+
+        #[arg(long)]
+        amount_e8s: u64,
+
+        #[command(flatten)]
+        dedup: Dedup,
+    },
+}
+
+// What the ledger identifies an update by. Rerunning with the same memo and timestamp within 24
+// hours returns the original block, rather than applying it twice.
+#[derive(Args, Debug)]
+struct Dedup {
+    #[arg(long, default_value = "0")]
+    memo: u64,
+
+    /// Timestamp (nanoseconds since the unix epoch) to send the update with. Defaults to now
+    #[arg(long)]
+    created_at_time: Option<u64>,
+}
+
+impl Dedup {
+    fn created_at_time(&self) -> anyhow::Result<u64> {
+        match self.created_at_time {
+            Some(created_at_time) => Ok(created_at_time),
+            None => Ok(SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_nanos() as u64),
+        }
+    }
+}
+
+impl Command {
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let agent = self.identity.create_local_agent().await?;
+        let sticp = icrc::Agent {
+            agent: &agent,
+            canister_id: self.ledger.sticp_ledger()?,
+            retry: self.identity.retry.policy(),
+        };
+
+        match &self.command {
+            SticpCommand::Balance { account, reads } => {
+                let account = match account {
+                    Some(account) => parse_account(account)?,
+                    None => icrc::Account {
+                        owner: agent.get_principal().map_err(|e| anyhow!(e))?,
+                        subaccount: None,
+                    },
+                };
+                println!("{}", sticp.balance_of(&account, *reads).await?);
+            }
+            SticpCommand::Approve {
+                spender,
+                amount_e8s,
+                expected_allowance_e8s,
+                expires_at,
+                dedup,
+            } => {
+                let created_at_time = dedup.created_at_time()?;
+                info!(
+                    spender,
+                    amount_e8s,
+                    memo = dedup.memo,
+                    created_at_time,
+                    "Approving. If this fails, rerun with --memo {} --created-at-time {} to retry",
+                    dedup.memo,
+                    created_at_time
+                );
+                let index = sticp
+                    .approve(&icrc::ApproveRequest {
+                        from_subaccount: None,
+                        spender: parse_account(spender)?,
+                        amount_e8s: *amount_e8s,
+                        expected_allowance_e8s: *expected_allowance_e8s,
+                        expires_at: *expires_at,
+                        memo: dedup.memo,
+                        created_at_time,
+                    })
+                    .await?;
+                println!("{}", index);
+            }
+            SticpCommand::TransferFrom {
+                from,
+                to,
+                amount_e8s,
+                dedup,
+            } => {
+                let created_at_time = dedup.created_at_time()?;
+                info!(
+                    from,
+                    to,
+                    amount_e8s,
+                    memo = dedup.memo,
+                    created_at_time,
+                    "Transferring. If this fails, rerun with --memo {} --created-at-time {} to \
+                     retry without sending twice",
+                    dedup.memo,
+                    created_at_time
+                );
+                let index = sticp
+                    .transfer_from(&icrc::TransferFromRequest {
+                        spender_subaccount: None,
+                        from: parse_account(from)?,
+                        to: parse_account(to)?,
+                        amount_e8s: *amount_e8s,
+                        memo: dedup.memo,
+                        created_at_time,
+                    })
+                    .await?;
+                println!("{}", index);
+            }
+        }
+        Ok(())
+    }
+}
+
+// An ICRC account, as <principal> or <principal>:<hex subaccount>.
+fn parse_account(account: &str) -> anyhow::Result<icrc::Account> {
+    let (owner, subaccount) = match account.split_once(':') {
+        Some((owner, subaccount)) => {
+            let subaccount = hex::decode(subaccount)?;
+            if subaccount.len() != 32 {
+                bail!("Subaccount of {} should be 32 bytes", account);
+            }
+            (owner, Some(subaccount))
+        }
+        None => (account, None),
+    };
+    Ok(icrc::Account {
+        owner: Principal::from_text(owner)?,
+        subaccount,
+    })
+}
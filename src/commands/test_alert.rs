@@ -0,0 +1,36 @@
+use anyhow::bail;
+use clap::Args;
+
+use crate::alert::{self, Event};
+
+#[derive(Args, Debug)]
+pub struct Command {
+    #[command(flatten)]
+    alert: alert::AlertArgs,
+
+    /// Message to include in the test alert
+    #[arg(long, default_value = "testing oracle alerts")]
+    message: String,
+}
+
+impl Command {
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let notifier = self.alert.notifier()?;
+        if !notifier.has_sinks() {
+            bail!(
+                "No alert sinks configured, set --alert-webhook, --alert-smtp-url or \
+                 --alert-command"
+            );
+        }
+        let sent = notifier
+            .notify(Event::Test {
+                message: self.message.clone(),
+            })
+            .await;
+        if !sent {
+            bail!("Couldn't send the test alert to every sink");
+        }
+        println!("Sent test alert");
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use clap::Args;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use crate::error::{OracleError, Result};
+
+// Commands which take an operator's own key, so never get settings from the oracle's config file.
+// Otherwise e.g. the oracle's private_pem or keystore would become their defaults.
+const UNCONFIGURED: &[&str] = &["approve", "encrypt-key"];
+
+#[derive(Args, Debug, Clone)]
+pub struct ConfigArgs {
+    /// TOML file to read settings from. Flags and environment variables override its settings
+    #[arg(long, global = true, env = "ORACLE_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Profile in the config file to use, e.g. mainnet, local or staging. Defaults to the file's
+    /// default_profile
+    #[arg(long, global = true, env = "ORACLE_PROFILE")]
+    pub profile: Option<String>,
+}
+
+// The config file. Top-level settings apply to every profile, and each profile's settings
+// override them. Settings are named after flags, e.g. `ic_url` or `ic-url` for --ic-url.
+#[derive(Deserialize, Debug, Default)]
+struct File {
+    default_profile: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, toml::Table>,
+    #[serde(flatten)]
+    settings: toml::Table,
+}
+
+// Settings from the selected profile of the config file, resolved to the values of the flags
+// they stand in for.
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    pub path: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub settings: BTreeMap<String, Vec<String>>,
+}
+
+impl Config {
+    // Load the config file and profile named by --config and --profile, or their environment
+    // variables. These are needed before the rest of the command line can be parsed, so are
+    // picked out of it by hand.
+    pub fn load() -> Result<Self> {
+        let profile = early_arg("profile").or_else(|| std::env::var("ORACLE_PROFILE").ok());
+        let Some(path) = early_arg("config").or_else(|| std::env::var("ORACLE_CONFIG").ok()) else {
+            if profile.is_some() {
+                return Err(OracleError::Config(
+                    "--profile needs a --config file".to_string(),
+                ));
+            }
+            return Ok(Self::default());
+        };
+        let path = PathBuf::from(path);
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| OracleError::Config(format!("Couldn't read {}: {e}", path.display())))?;
+        let file: File = toml::from_str(&contents)
+            .map_err(|e| OracleError::Config(format!("Couldn't parse {}: {e}", path.display())))?;
+
+        let profile = profile.or(file.default_profile);
+        let mut settings = file.settings;
+        if let Some(name) = &profile {
+            let overrides = file.profiles.get(name).ok_or_else(|| {
+                OracleError::Config(format!(
+                    "No profile {} in {}, expected one of: {}",
+                    name,
+                    path.display(),
+                    file.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                ))
+            })?;
+            settings.extend(overrides.clone());
+        }
+
+        let settings = settings
+            .into_iter()
+            .map(|(key, value)| Ok((key.replace('-', "_"), values(&key, value)?)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            path: Some(path),
+            profile,
+            settings,
+        })
+    }
+
+    // The early scan of the command line can't tell a flag from a value which happens to look
+    // like one, so make sure clap agrees on which file and profile were asked for.
+    pub fn check(&self, args: &ConfigArgs) -> Result<()> {
+        if args.config != self.path || (args.profile.is_some() && args.profile != self.profile) {
+            return Err(OracleError::Config(
+                "Couldn't tell which --config and --profile to use, try setting ORACLE_CONFIG \
+                 and ORACLE_PROFILE instead"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Use the config's settings as the defaults of the flags they name, throughout the command
+    // tree. Environment variables and flags still take precedence.
+    pub fn apply(&self, command: clap::Command) -> Result<clap::Command> {
+        for key in self.settings.keys() {
+            if !configures(&command, key) {
+                return Err(OracleError::Config(format!(
+                    "Unknown setting {} in {}",
+                    key,
+                    self.path.clone().unwrap_or_default().display()
+                )));
+            }
+        }
+        Ok(self.apply_defaults(command))
+    }
+
+    fn apply_defaults(&self, mut command: clap::Command) -> clap::Command {
+        let ids: Vec<(String, String)> = command
+            .get_arguments()
+            .filter_map(|arg| {
+                let id = arg.get_id().to_string();
+                let key = setting_key(arg)
+                    .into_iter()
+                    .find(|k| self.settings.contains_key(k))?;
+                Some((id, key))
+            })
+            .collect();
+        for (id, key) in ids {
+            let values = self.settings[&key].clone();
+            command = command.mut_arg(id, |arg| match values.len() {
+                1 => arg.default_value(values[0].clone()),
+                _ => arg.default_values(values),
+            });
+        }
+
+        let subcommands: Vec<String> = command
+            .get_subcommands()
+            .map(|c| c.get_name().to_string())
+            .filter(|name| !UNCONFIGURED.contains(&name.as_str()))
+            .collect();
+        for name in subcommands {
+            command = command.mut_subcommand(name, |c| self.apply_defaults(c));
+        }
+        command
+    }
+
+    // The config setting the flag's default came from, if any.
+    pub fn setting_for(&self, arg: &clap::Arg) -> Option<&str> {
+        setting_key(arg)
+            .into_iter()
+            .find_map(|k| self.settings.get_key_value(&k).map(|(k, _)| k.as_str()))
+    }
+}
+
+// The names a setting for the flag may be given as: its id, or its long name.
+fn setting_key(arg: &clap::Arg) -> Vec<String> {
+    let mut keys = vec![arg.get_id().to_string()];
+    if let Some(long) = arg.get_long() {
+        keys.push(long.replace('-', "_"));
+    }
+    keys
+}
+
+// Whether any command in the tree which takes settings has a flag the setting could be for.
+fn configures(command: &clap::Command, key: &str) -> bool {
+    command
+        .get_arguments()
+        .any(|arg| setting_key(arg).iter().any(|k| k == key))
+        || command
+            .get_subcommands()
+            .filter(|c| !UNCONFIGURED.contains(&c.get_name()))
+            .any(|c| configures(c, key))
+}
+
+// A setting's value, as the flag value(s) it stands in for.
+fn values(key: &str, value: toml::Value) -> Result<Vec<String>> {
+    match value {
+        toml::Value::Array(items) => items.into_iter().map(|v| scalar(key, v)).collect(),
+        value => Ok(vec![scalar(key, value)?]),
+    }
+}
+
+fn scalar(key: &str, value: toml::Value) -> Result<String> {
+    match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(OracleError::Config(format!(
+            "Setting {key} must be a string, number, boolean or array of them"
+        ))),
+    }
+}
+
+// The value of a global flag, found before the command line is parsed.
+fn early_arg(name: &str) -> Option<String> {
+    let flag = format!("--{name}");
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == flag {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix(&format!("{flag}=")) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
@@ -1,14 +1,18 @@
-use anyhow::anyhow;
 use async_trait::async_trait;
 use candid::{CandidType, Decode, Encode, Principal};
 use ic_base_types::PrincipalId;
 use ic_nns_governance::pb::v1::neuron::DissolveState;
 use icp_ledger::AccountIdentifier;
 use serde::Deserialize;
+use tracing::instrument;
+
+use crate::error::{OracleError, Result};
+use crate::query::{self, ReadMode};
+use crate::retry;
 
 #[async_trait]
 pub trait Service {
-    async fn list_neurons_to_disburse(&self, now: u64) -> anyhow::Result<Vec<u64>>;
+    async fn list_neurons_to_disburse(&self, now: u64, mode: ReadMode) -> Result<Vec<u64>>;
 
     // This will do all of the following in the canister:
     //
@@ -34,17 +38,41 @@ pub trait Service {
     //
     // This is all done in a single call, so that it is more atomic (not fully), and there is less
     // back-and-forth between this script and the canister.
-    async fn refresh_neurons_and_apply_interest(&self) -> anyhow::Result<Vec<(u64, u64, bool)>>;
+    async fn refresh_neurons_and_apply_interest(&self) -> Result<Vec<(u64, u64, bool)>>;
+
+    // Read-only counterpart of refresh_neurons_and_apply_interest. Returns the splits the canister
+    // would currently ask for, without applying interest, flushing deposits, or updating any state.
+    async fn preview_neurons_to_split(&self) -> Result<Vec<(u64, u64, bool)>>;
+
+    async fn replace_staking_neuron(&self, old_id: u64, new_id: u64) -> Result<()>;
+
+    async fn list_staking_neurons(&self, mode: ReadMode) -> Result<Vec<u64>>;
+
+    // All withdrawal neurons, with the stake and dissolve state the canister has cached for them.
+    async fn list_withdrawal_neurons(&self, mode: ReadMode) -> Result<Vec<Neuron>>;
+
+    // Every neuron the canister tracks, both staking and withdrawal neurons.
+    async fn list_neurons(&self, mode: ReadMode) -> Result<Vec<u64>> {
+        let mut ids = self.list_staking_neurons(mode).await?;
+        ids.extend(self.list_withdrawal_neurons(mode).await?.iter().map(|n| n.id));
+        Ok(ids)
+    }
+
+    // Have the canister re-read the given neurons from governance and update its cached stake and
+    // dissolve state. Unlike refresh_neurons_and_apply_interest, this applies no interest.
+    async fn refresh_neurons(&self, ids: Vec<u64>) -> Result<()>;
 
-    async fn replace_staking_neuron(&self, old_id: u64, new_id: u64) -> anyhow::Result<()>;
+    // The mean APR the canister has paid out, in microbips, as updated when interest is applied.
+    async fn apr_microbips(&self) -> Result<u64>;
 
     // Calculate the deposit canister's account id for disbursing neurons to
-    fn account_id(&self) -> anyhow::Result<AccountIdentifier>;
+    fn account_id(&self) -> Result<AccountIdentifier>;
 }
 
 pub struct Agent<'a> {
     pub agent: &'a ic_agent::Agent,
     pub canister_id: Principal,
+    pub retry: retry::Policy,
 }
 
 #[derive(CandidType)]
@@ -66,11 +94,30 @@ pub struct Neuron {
     pub staked_maturity_e8s_equivalent: Option<u64>,
 }
 
+#[derive(CandidType, Deserialize)]
+pub struct StakingNeuronId {
+    pub id: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct StakingNeuron {
+    pub id: StakingNeuronId,
+    #[serde(rename = "accountId")]
+    pub account_id: String,
+}
+
+pub type StakingNeuronsResult = Vec<StakingNeuron>;
+
 #[derive(CandidType)]
 pub struct RefreshNeuronsAndApplyInterestArgs {}
 
 pub type RefreshNeuronsAndApplyInterestResult = Vec<(u64, u64, bool)>;
 
+#[derive(CandidType)]
+pub struct PreviewNeuronsToSplitArgs {}
+
+pub type PreviewNeuronsToSplitResult = Vec<(u64, u64, bool)>;
+
 #[derive(CandidType)]
 pub struct ReplaceNeuronArgs {
     pub old_id: u64,
@@ -81,16 +128,11 @@ pub type ReplaceNeuronResult = ();
 
 #[async_trait]
 impl Service for Agent<'_> {
-    async fn list_neurons_to_disburse(&self, now: u64) -> anyhow::Result<Vec<u64>> {
-        let response = self
-            .agent
-            .update(&self.canister_id, "listNeuronsToDisburse")
-            .with_arg(&Encode!(&ListNeuronsToDisburseArgs {})?)
-            .call_and_wait()
-            .await?;
-
-        let result = Decode!(response.as_slice(), ListNeuronsToDisburseResult)
-            .map_err(|err| anyhow!(err))?
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn list_neurons_to_disburse(&self, now: u64, mode: ReadMode) -> Result<Vec<u64>> {
+        let result = self
+            .list_withdrawal_neurons(mode)
+            .await?
             .iter()
             .filter(|n| {
                 let Some(DissolveState::WhenDissolvedTimestampSeconds(dissolved_at)) = n.dissolve_state else {
@@ -106,32 +148,134 @@ impl Service for Agent<'_> {
         Ok(result)
     }
 
-    async fn refresh_neurons_and_apply_interest(&self) -> anyhow::Result<Vec<(u64, u64, bool)>> {
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn refresh_neurons_and_apply_interest(&self) -> Result<Vec<(u64, u64, bool)>> {
+        // Not retried: this mints interest, and there's no way to tell whether a failed attempt
+        // took effect. Running the daily job again repeats it deliberately.
+        let arg = Encode!(&RefreshNeuronsAndApplyInterestArgs {})?;
+        let response = retry::Policy::once()
+            .retry("refreshNeuronsAndApplyInterest", || async {
+                Ok(self
+                    .agent
+                    .update(&self.canister_id, "refreshNeuronsAndApplyInterest")
+                    .with_arg(&arg)
+                    .call_and_wait()
+                    .await?)
+            })
+            .await?;
+
+        let result = Decode!(response.as_slice(), RefreshNeuronsAndApplyInterestResult)?;
+        Ok(result)
+    }
+
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn preview_neurons_to_split(&self) -> Result<Vec<(u64, u64, bool)>> {
+        let arg = Encode!(&PreviewNeuronsToSplitArgs {})?;
         let response = self
-            .agent
-            .update(&self.canister_id, "refreshNeuronsAndApplyInterest")
-            .with_arg(&Encode!(&RefreshNeuronsAndApplyInterestArgs {})?)
-            .call_and_wait()
+            .retry
+            .retry("previewNeuronsToSplit", || {
+                query::read(
+                    self.agent,
+                    &self.canister_id,
+                    "previewNeuronsToSplit",
+                    &arg,
+                    ReadMode::Query,
+                )
+            })
             .await?;
 
-        let result = Decode!(response.as_slice(), RefreshNeuronsAndApplyInterestResult)
-            .map_err(|err| anyhow!(err))?;
+        let result = Decode!(response.as_slice(), PreviewNeuronsToSplitResult)?;
         Ok(result)
     }
 
-    async fn replace_staking_neuron(&self, old_id: u64, new_id: u64) -> anyhow::Result<()> {
-        self
-            .agent
-            .update(&self.canister_id, "replaceStakingNeuron")
-            .with_arg(&Encode!(&ReplaceNeuronArgs { old_id, new_id })?)
-            .call_and_wait()
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn replace_staking_neuron(&self, old_id: u64, new_id: u64) -> Result<()> {
+        // Replacing twice is rejected, as the old neuron is no longer staking. So check the
+        // staking neurons first, both before calling (a resumed run repeats this call) and before
+        // each retry.
+        let replaced = || async {
+            let staking = self.list_staking_neurons(ReadMode::Certified).await?;
+            Ok((staking.contains(&new_id) && !staking.contains(&old_id)).then_some(()))
+        };
+        if replaced().await?.is_some() {
+            return Ok(());
+        }
+        let arg = Encode!(&ReplaceNeuronArgs { old_id, new_id })?;
+        self.retry
+            .retry_verified(
+                "replaceStakingNeuron",
+                || async {
+                    self.agent
+                        .update(&self.canister_id, "replaceStakingNeuron")
+                        .with_arg(&arg)
+                        .call_and_wait()
+                        .await?;
+                    Ok(())
+                },
+                replaced,
+            )
+            .await
+    }
+
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn list_staking_neurons(&self, mode: ReadMode) -> Result<Vec<u64>> {
+        let arg = Encode!()?;
+        let response = self
+            .retry
+            .retry("stakingNeurons", || {
+                query::read(self.agent, &self.canister_id, "stakingNeurons", &arg, mode)
+            })
+            .await?;
+        Ok(Decode!(response.as_slice(), StakingNeuronsResult)?
+            .into_iter()
+            .map(|n| n.id.id)
+            .collect())
+    }
+
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn list_withdrawal_neurons(&self, mode: ReadMode) -> Result<Vec<Neuron>> {
+        let arg = Encode!(&ListNeuronsToDisburseArgs {})?;
+        let response = self
+            .retry
+            .retry("listNeuronsToDisburse", || {
+                query::read(self.agent, &self.canister_id, "listNeuronsToDisburse", &arg, mode)
+            })
+            .await?;
+        Ok(Decode!(response.as_slice(), ListNeuronsToDisburseResult)?)
+    }
+
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn refresh_neurons(&self, ids: Vec<u64>) -> Result<()> {
+        // Only updates the canister's cache from governance, so is always safe to retry.
+        let arg = Encode!(&ids)?;
+        self.retry
+            .retry("refreshNeurons", || async {
+                Ok(self
+                    .agent
+                    .update(&self.canister_id, "refreshNeurons")
+                    .with_arg(&arg)
+                    .call_and_wait()
+                    .await?)
+            })
             .await?;
         Ok(())
     }
 
-    fn account_id(&self) -> anyhow::Result<AccountIdentifier> {
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn apr_microbips(&self) -> Result<u64> {
+        let arg = Encode!()?;
+        let response = self
+            .retry
+            .retry("aprMicrobips", || {
+                query::read(self.agent, &self.canister_id, "aprMicrobips", &arg, ReadMode::Query)
+            })
+            .await?;
+        Ok(Decode!(response.as_slice(), u64)?)
+    }
+
+    fn account_id(&self) -> Result<AccountIdentifier> {
         PrincipalId::try_from(self.canister_id.as_slice())
             .map(|p| AccountIdentifier::new(p, None))
-            .map_err(|err| anyhow!(err))
+            .map_err(|err| OracleError::Config(err.to_string()))
     }
 }
@@ -0,0 +1,159 @@
+use ic_agent::AgentError;
+use icp_ledger::TransferError;
+use std::{fmt, path::PathBuf};
+
+use crate::governance;
+use crate::ledger::icrc;
+use crate::strategy;
+
+pub type Result<T> = std::result::Result<T, OracleError>;
+
+// Errors returned by all of the canister clients. Each variant is a different class of failure,
+// so callers can decide whether to retry or abort, and the process can exit with a distinct code.
+#[derive(Debug)]
+pub enum OracleError {
+    // Couldn't reach the replica, or timed out waiting for a response.
+    Transport(String),
+    // Certificate verification failed, or the root key couldn't be fetched or is wrong.
+    Certificate(String),
+    // Couldn't encode the request or decode the response.
+    Candid(String),
+    // The canister (or replica) rejected the call.
+    Reject { code: u64, message: String },
+    // Governance returned an error for a neuron command.
+    Governance {
+        neuron_id: Option<u64>,
+        error: governance::Error,
+    },
+    // The ledger returned an error for a transfer.
+    Ledger(TransferError),
+    // An ICRC-1/ICRC-2 ledger returned an error for a transfer or approval.
+    IcrcLedger(icrc::Error),
+    // The signing canister failed to produce a key or signature.
+    Signer(String),
+    // Bad configuration, such as an invalid principal.
+    Config(String),
+    // The operation needs approval from more operators before it can be submitted.
+    ApprovalRequired {
+        request: PathBuf,
+        approvals: usize,
+        required: usize,
+    },
+    // An approval request file couldn't be read, written, or is malformed.
+    Approval(String),
+    // A disburse failed the safety checks, so was never sent.
+    DisburseRefused {
+        neuron_id: u64,
+        reason: governance::Refusal,
+    },
+    // A split plan failed the strategy's checks, so none of it was sent.
+    SplitRefused(strategy::Divergence),
+}
+
+impl OracleError {
+    pub fn governance(neuron_id: Option<u64>, error: governance::Error) -> Self {
+        OracleError::Governance { neuron_id, error }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            OracleError::Config(_) => 2,
+            OracleError::Transport(_) => 10,
+            OracleError::Certificate(_) => 11,
+            OracleError::Candid(_) => 12,
+            OracleError::Reject { .. } => 13,
+            OracleError::Governance { .. } => 14,
+            OracleError::Ledger(_) | OracleError::IcrcLedger(_) => 15,
+            OracleError::Signer(_) => 16,
+            OracleError::ApprovalRequired { .. } | OracleError::Approval(_) => 17,
+            OracleError::DisburseRefused { .. } => 18,
+            OracleError::SplitRefused(_) => 19,
+        }
+    }
+}
+
+impl fmt::Display for OracleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OracleError::Transport(m) => write!(f, "transport error: {}", m),
+            OracleError::Certificate(m) => write!(f, "certificate error: {}", m),
+            OracleError::Candid(m) => write!(f, "candid error: {}", m),
+            OracleError::Reject { code, message } => {
+                write!(f, "call rejected, code: {}, message: {}", code, message)
+            }
+            OracleError::Governance {
+                neuron_id: Some(id),
+                error,
+            } => write!(f, "governance error for neuron {}: {}", id, error),
+            OracleError::Governance {
+                neuron_id: None,
+                error,
+            } => write!(f, "governance error: {}", error),
+            OracleError::Ledger(err) => write!(f, "ledger error: {}", err),
+            OracleError::IcrcLedger(err) => write!(f, "ledger error: {}", err),
+            OracleError::Signer(m) => write!(f, "signer error: {}", m),
+            OracleError::Config(m) => write!(f, "invalid configuration: {}", m),
+            OracleError::ApprovalRequired {
+                request,
+                approvals,
+                required,
+            } => write!(
+                f,
+                "operation needs approval, {} of {} operators have approved {}",
+                approvals,
+                required,
+                request.display()
+            ),
+            OracleError::Approval(m) => write!(f, "approval error: {}", m),
+            OracleError::DisburseRefused { neuron_id, reason } => {
+                write!(f, "refusing to disburse neuron {}: {}", neuron_id, reason)
+            }
+            OracleError::SplitRefused(divergence) => {
+                write!(f, "refusing to split: {}", divergence)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OracleError {}
+
+impl From<AgentError> for OracleError {
+    fn from(err: AgentError) -> Self {
+        match err {
+            AgentError::ReplicaError {
+                reject_code,
+                reject_message,
+            } => OracleError::Reject {
+                code: reject_code,
+                message: reject_message,
+            },
+            AgentError::CertificateVerificationFailed()
+            | AgentError::CertificateNotAuthorized()
+            | AgentError::DerKeyLengthMismatch { .. }
+            | AgentError::DerPrefixMismatch { .. }
+            | AgentError::NoRootKeyInStatus(_) => OracleError::Certificate(err.to_string()),
+            AgentError::CandidError(_) => OracleError::Candid(err.to_string()),
+            AgentError::SigningError(m) => OracleError::Signer(m),
+            _ => OracleError::Transport(err.to_string()),
+        }
+    }
+}
+
+impl From<candid::Error> for OracleError {
+    fn from(err: candid::Error) -> Self {
+        OracleError::Candid(err.to_string())
+    }
+}
+
+impl From<TransferError> for OracleError {
+    fn from(err: TransferError) -> Self {
+        OracleError::Ledger(err)
+    }
+}
+
+// Pick the exit code for a failed command from the first OracleError in its chain of causes.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<OracleError>())
+        .map_or(1, |e| e.exit_code())
+}
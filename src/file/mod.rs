@@ -0,0 +1,11 @@
+use std::{fs, io::Write, path::Path};
+
+// Replace the file at `path` with `contents`, so it is never seen half written: the contents go to
+// a temporary file next to it, which is synced to disk, then renamed over it.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
@@ -0,0 +1,164 @@
+use ic_nns_governance::pb::v1::{
+    governance_error::ErrorType, manage_neuron::Command, manage_neuron_response, GovernanceError,
+};
+use std::fmt;
+
+// Everything that can go wrong with a governance call after the response has been received and
+// decoded. Each governance ErrorType maps to its own variant, so callers can decide what to do
+// about, for example, an unauthorized caller vs a neuron that doesn't have enough stake.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Unavailable(String),
+    NotAuthorized(String),
+    NotFound(String),
+    InvalidCommand(String),
+    RequiresNotDissolving(String),
+    RequiresDissolving(String),
+    RequiresDissolved(String),
+    HotKey(String),
+    ResourceExhausted(String),
+    PreconditionFailed(String),
+    External(String),
+    LedgerUpdateOngoing(String),
+    InsufficientFunds(String),
+    InvalidPrincipal(String),
+    InvalidProposal(String),
+    AlreadyJoinedCommunityFund(String),
+    NotInTheCommunityFund(String),
+    // Governance returned an error type this client doesn't know about (or "unspecified").
+    Unknown { error_type: i32, message: String },
+    // Governance returned no command at all in the ManageNeuronResponse.
+    EmptyResponse { request: &'static str },
+    // Governance responded to a different command than the one we sent.
+    MismatchedResponse {
+        request: &'static str,
+        response: &'static str,
+    },
+    // The response matched the command, but was missing data we need (e.g. the new neuron id).
+    MalformedResponse(String),
+}
+
+impl From<GovernanceError> for Error {
+    fn from(err: GovernanceError) -> Self {
+        let message = err.error_message;
+        match ErrorType::from_i32(err.error_type) {
+            Some(ErrorType::Unavailable) => Error::Unavailable(message),
+            Some(ErrorType::NotAuthorized) => Error::NotAuthorized(message),
+            Some(ErrorType::NotFound) => Error::NotFound(message),
+            Some(ErrorType::InvalidCommand) => Error::InvalidCommand(message),
+            Some(ErrorType::RequiresNotDissolving) => Error::RequiresNotDissolving(message),
+            Some(ErrorType::RequiresDissolving) => Error::RequiresDissolving(message),
+            Some(ErrorType::RequiresDissolved) => Error::RequiresDissolved(message),
+            Some(ErrorType::HotKey) => Error::HotKey(message),
+            Some(ErrorType::ResourceExhausted) => Error::ResourceExhausted(message),
+            Some(ErrorType::PreconditionFailed) => Error::PreconditionFailed(message),
+            Some(ErrorType::External) => Error::External(message),
+            Some(ErrorType::LedgerUpdateOngoing) => Error::LedgerUpdateOngoing(message),
+            Some(ErrorType::InsufficientFunds) => Error::InsufficientFunds(message),
+            Some(ErrorType::InvalidPrincipal) => Error::InvalidPrincipal(message),
+            Some(ErrorType::InvalidProposal) => Error::InvalidProposal(message),
+            Some(ErrorType::AlreadyJoinedCommunityFund) => {
+                Error::AlreadyJoinedCommunityFund(message)
+            }
+            Some(ErrorType::NotInTheCommunityFund) => Error::NotInTheCommunityFund(message),
+            _ => Error::Unknown {
+                error_type: err.error_type,
+                message,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unavailable(m) => write!(f, "governance unavailable: {}", m),
+            Error::NotAuthorized(m) => write!(f, "not authorized: {}", m),
+            Error::NotFound(m) => write!(f, "not found: {}", m),
+            Error::InvalidCommand(m) => write!(f, "invalid command: {}", m),
+            Error::RequiresNotDissolving(m) => write!(f, "neuron must not be dissolving: {}", m),
+            Error::RequiresDissolving(m) => write!(f, "neuron must be dissolving: {}", m),
+            Error::RequiresDissolved(m) => write!(f, "neuron must be dissolved: {}", m),
+            Error::HotKey(m) => write!(f, "hot key error: {}", m),
+            Error::ResourceExhausted(m) => write!(f, "resource exhausted: {}", m),
+            Error::PreconditionFailed(m) => write!(f, "precondition failed: {}", m),
+            Error::External(m) => write!(f, "external error: {}", m),
+            Error::LedgerUpdateOngoing(m) => write!(f, "ledger update ongoing: {}", m),
+            Error::InsufficientFunds(m) => write!(f, "insufficient funds: {}", m),
+            Error::InvalidPrincipal(m) => write!(f, "invalid principal: {}", m),
+            Error::InvalidProposal(m) => write!(f, "invalid proposal: {}", m),
+            Error::AlreadyJoinedCommunityFund(m) => {
+                write!(f, "already joined community fund: {}", m)
+            }
+            Error::NotInTheCommunityFund(m) => write!(f, "not in the community fund: {}", m),
+            Error::Unknown {
+                error_type,
+                message,
+            } => write!(f, "governance error type {}: {}", error_type, message),
+            Error::EmptyResponse { request } => {
+                write!(f, "empty response to {} command", request)
+            }
+            Error::MismatchedResponse { request, response } => write!(
+                f,
+                "sent {} command, but got {} response",
+                request, response
+            ),
+            Error::MalformedResponse(m) => write!(f, "malformed response: {}", m),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub fn command_name(command: &Command) -> &'static str {
+    match command {
+        Command::Spawn(_) => "Spawn",
+        Command::Split(_) => "Split",
+        Command::Follow(_) => "Follow",
+        Command::ClaimOrRefresh(_) => "ClaimOrRefresh",
+        Command::Configure(_) => "Configure",
+        Command::RegisterVote(_) => "RegisterVote",
+        Command::Merge(_) => "Merge",
+        Command::DisburseToNeuron(_) => "DisburseToNeuron",
+        Command::MakeProposal(_) => "MakeProposal",
+        Command::StakeMaturity(_) => "StakeMaturity",
+        Command::MergeMaturity(_) => "MergeMaturity",
+        Command::Disburse(_) => "Disburse",
+    }
+}
+
+pub fn response_name(response: &manage_neuron_response::Command) -> &'static str {
+    use manage_neuron_response::Command as Response;
+    match response {
+        Response::Error(_) => "Error",
+        Response::Spawn(_) => "Spawn",
+        Response::Split(_) => "Split",
+        Response::Follow(_) => "Follow",
+        Response::MakeProposal(_) => "MakeProposal",
+        Response::RegisterVote(_) => "RegisterVote",
+        Response::Configure(_) => "Configure",
+        Response::Disburse(_) => "Disburse",
+        Response::ClaimOrRefresh(_) => "ClaimOrRefresh",
+        Response::MergeMaturity(_) => "MergeMaturity",
+        Response::Merge(_) => "Merge",
+        Response::DisburseToNeuron(_) => "DisburseToNeuron",
+        Response::StakeMaturity(_) => "StakeMaturity",
+    }
+}
+
+// Turn a ManageNeuronResponse into either the response command, or an Error. A governance error,
+// an empty response, and a response to a different command are all errors.
+pub fn check_response(
+    request: &'static str,
+    response: Option<manage_neuron_response::Command>,
+) -> Result<manage_neuron_response::Command, Error> {
+    match response {
+        None => Err(Error::EmptyResponse { request }),
+        Some(manage_neuron_response::Command::Error(err)) => Err(err.into()),
+        Some(command) if response_name(&command) == request => Ok(command),
+        Some(command) => Err(Error::MismatchedResponse {
+            request,
+            response: response_name(&command),
+        }),
+    }
+}
@@ -1,4 +1,3 @@
-use anyhow::{anyhow, bail};
 use async_trait::async_trait;
 use candid::{CandidType, Decode, Deserialize, Encode, Principal};
 use ic_base_types::PrincipalId;
@@ -15,53 +14,72 @@ use ic_nns_governance::pb::v1::{
     ListNeuronsResponse, ManageNeuron, ManageNeuronResponse, Neuron,
 };
 use icp_ledger::AccountIdentifier;
+use std::time::SystemTime;
+use tracing::{error, info, instrument, warn};
 
-const ICP_FEE: u64 = 10_000;
+use crate::approval;
+use crate::error::{OracleError, Result};
+use crate::metrics;
+use crate::query::{self, ReadMode};
+use crate::retry;
+
+mod error;
+mod safety;
+
+pub use error::Error;
+pub use safety::{DisburseArgs, DisburseSafety, Refusal};
+
+pub const ICP_FEE: u64 = 10_000;
 
 #[async_trait]
 pub trait Service {
     // Disburse all disburseable neurons to the target address
     // 1. Fetch a list of any disburseable neurons from the governance service
     // 2. Disburse any disburseable neurons into the deposits canister
-    async fn disburse_neurons(&self, address: &AccountIdentifier, neurons: &[u64]) -> anyhow::Result<()>;
-
-    // Apply the given list of neuron splits, adding the given hotkeys to each new neuron, and
-    // starting the new neurons dissolving.
-    //
-    // These steps are handled previously in canister updates (for better atomicity), and passed in.
-    // 1. Query dissolving neurons total & pending total, to calculate dissolving target from the
-    //    Deposits service
-    // 2. Calculate which staking neurons to split and how much
-    // 3. Split & dissolve new neurons as needed
-    async fn split_new_withdrawal_neurons(
-        &self,
-        neurons_to_split: Vec<(u64, u64, bool)>,
-    ) -> anyhow::Result<Vec<(u64, u64)>>;
+    async fn disburse_neurons(&self, address: &AccountIdentifier, neurons: &[u64]) -> Result<()>;
 
-    async fn claim_neuron(&self, controller: Option<Principal>, memo: u64) -> anyhow::Result<u64>;
+    // Split `amount_e8s` off of the given neuron, returning the id of the newly created neuron.
+    async fn split_neuron(&self, neuron_id: u64, amount_e8s: u64) -> Result<u64>;
+    async fn start_dissolving(&self, neuron_id: u64) -> Result<()>;
+
+    async fn claim_neuron(&self, controller: Option<Principal>, memo: u64) -> Result<u64>;
     async fn increase_neuron_delay(
         &self,
         neuron_id: u64,
         additional_dissolve_delay_seconds: u32,
-    ) -> anyhow::Result<()>;
-    async fn add_hotkey(&self, neuron_id: u64, key: Principal) -> anyhow::Result<()>;
-    async fn enable_auto_merge_maturity(&self, neuron_id: u64) -> anyhow::Result<()>;
+    ) -> Result<()>;
+    async fn add_hotkey(&self, neuron_id: u64, key: Principal) -> Result<()>;
+    async fn enable_auto_merge_maturity(&self, neuron_id: u64) -> Result<()>;
+
+    // List the given neurons, plus all neurons readable by the caller (i.e. those it controls or
+    // is a hotkey of).
+    async fn list_neurons(&self, neuron_ids: Vec<u64>, mode: ReadMode) -> Result<Vec<Neuron>>;
 
     // Calculate the governance canister's account id for creating new neurons
-    fn account_id(&self) -> anyhow::Result<AccountIdentifier>;
+    fn account_id(&self) -> Result<AccountIdentifier>;
+
+    // Check the operation has any operator approvals it needs, without submitting it.
+    fn check_approval(&self, operation: &approval::Operation) -> Result<()>;
 }
 
 pub struct Agent<'a> {
     pub agent: &'a ic_agent::Agent,
     pub canister_id: Principal,
+    pub retry: retry::Policy,
+    pub approvals: approval::Policy,
+    pub disburse_safety: DisburseSafety,
 }
 
 impl Agent<'_> {
+    // Send a manage_neuron command, and check that governance actually performed it. Governance
+    // errors come back as an OracleError::Governance, rather than as a "successful" response.
+    #[instrument(skip_all, fields(neuron_id = id, command = error::command_name(&command)))]
     async fn manage_neuron(
         &self,
         id: u64,
         command: Command,
-    ) -> anyhow::Result<ManageNeuronResponse> {
+    ) -> Result<manage_neuron_response::Command> {
+        let request = error::command_name(&command);
         let response = self
             .agent
             .update(&self.canister_id, "manage_neuron")
@@ -73,154 +91,340 @@ impl Agent<'_> {
             .call_and_wait()
             .await?;
 
-        Decode!(response.as_slice(), ManageNeuronResponse).map_err(|err| anyhow!(err))
+        let result = Decode!(response.as_slice(), ManageNeuronResponse)?;
+        error::check_response(request, result.command)
+            .map_err(|err| OracleError::governance(Some(id), err))
+    }
+
+    // Reads used to decide whether to retry a command, so always certified.
+    async fn get_neuron(&self, id: u64) -> Result<Option<Neuron>> {
+        Ok(self
+            .list_neurons(vec![id], ReadMode::Certified)
+            .await?
+            .into_iter()
+            .find(|n| n.id.as_ref().map(|n| n.id) == Some(id)))
     }
 }
 
 #[async_trait]
 impl Service for Agent<'_> {
-    async fn disburse_neurons(&self, address: &AccountIdentifier, neurons: &[u64]) -> anyhow::Result<()> {
+    #[instrument(
+        skip_all,
+        fields(canister = %self.canister_id, to = %address, neurons = ?neurons),
+        err
+    )]
+    async fn disburse_neurons(&self, address: &AccountIdentifier, neurons: &[u64]) -> Result<()> {
+        let controller = self.agent.get_principal().map_err(OracleError::Signer)?;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|e| OracleError::Config(e.to_string()))?
+            .as_secs();
         for id in neurons.iter() {
-            eprintln!("Disbursing neuron {} to {}", id, address);
-            self.manage_neuron(
-                id.clone(),
-                Command::Disburse(Disburse {
-                    to_account: Some(icp_ledger::protobuf::AccountIdentifier {
-                        hash: address.hash.try_into()?,
-                    }),
-                    amount: None, // all
+            // Check against a fresh read of the neuron, not whatever list the id came from.
+            let neuron = self.get_neuron(*id).await?;
+            let stake_e8s = neuron.as_ref().map_or(0, |n| {
+                n.cached_neuron_stake_e8s.saturating_sub(n.neuron_fees_e8s)
+            });
+            match self
+                .disburse_safety
+                .check(neuron.as_ref(), address, controller, now)
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!(neuron_id = id, "Neuron is already disbursed");
+                    continue;
+                }
+                Err(reason) => {
+                    error!(
+                        neuron_id = id,
+                        to = %address,
+                        refusal = ?reason,
+                        "Refused to disburse neuron: {}",
+                        reason
+                    );
+                    return Err(OracleError::DisburseRefused {
+                        neuron_id: *id,
+                        reason,
+                    });
+                }
+            }
+
+            let operation = approval::Operation::Disburse {
+                neuron_id: *id,
+                to: address.to_hex(),
+            };
+            self.approvals.check(&operation)?;
+            info!(neuron_id = id, to = %address, stake_e8s, "Disbursing neuron");
+            let command = Command::Disburse(Disburse {
+                to_account: Some(icp_ledger::protobuf::AccountIdentifier {
+                    hash: address.hash.to_vec(),
                 }),
-            )
-            .await?;
+                amount: None, // all
+            });
+            // Disbursing twice would fail, so check if the stake is already gone before retrying.
+            self.retry
+                .retry_verified(
+                    "disburse",
+                    || self.manage_neuron(*id, command.clone()),
+                    || async {
+                        let disbursed = self
+                            .get_neuron(*id)
+                            .await?
+                            .map_or(true, |n| n.cached_neuron_stake_e8s == 0);
+                        Ok(disbursed
+                            .then(|| manage_neuron_response::Command::Disburse(Default::default())))
+                    },
+                )
+                .await?;
+            // The neuron is already disbursed, so failing now would only hide that from the journal.
+            if let Err(err) = self.approvals.consume(&operation) {
+                warn!(neuron_id = id, %err, "Disbursed, but couldn't mark the approval as used");
+            }
+            metrics::ICP_MOVED_E8S
+                .with_label_values(&["disburse"])
+                .inc_by(stake_e8s);
         }
         Ok(())
     }
 
-    async fn split_new_withdrawal_neurons(
-        &self,
-        neurons_to_split: Vec<(u64, u64, bool)>,
-    ) -> anyhow::Result<Vec<(u64, u64)>> {
-        let mut replacements: Vec<(u64, u64)> = vec![];
-        for (id, amount_e8s, should_replace) in neurons_to_split.iter() {
-            eprintln!("Splitting neuron {}, amount {}, replacing {}", id, amount_e8s, should_replace);
-            let ManageNeuronResponse{
-                command: Some(manage_neuron_response::Command::Split(SplitResponse {
-                    created_neuron_id: Some(NeuronId {
-                        id: new_id,
-                    }),
-                }))
-            } = self.manage_neuron(
-                id.clone(),
-                Command::Split(Split { amount_e8s: amount_e8s.clone() }),
-            )
-            .await? else {
-                bail!("Unexpected response when splitting neuron {}", id)
-            };
-            eprintln!("Created new neuron {}", new_id);
-
-            if *should_replace {
-                replacements.push((id.clone(), new_id));
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn split_neuron(&self, neuron_id: u64, amount_e8s: u64) -> Result<u64> {
+        let operation = approval::Operation::Split {
+            neuron_id,
+            amount_e8s,
+        };
+        self.approvals.check(&operation)?;
 
-                // Start the old neuron dissolving
-                self.manage_neuron(
-                    id.clone(),
-                    Command::Configure(Configure {
-                        operation: Some(Operation::StartDissolving(StartDissolving {})),
-                    }),
-                )
-                .await?;
-                eprintln!("Started dissolving neuron {}", id);
-            } else {
-                // Start the new neuron dissolving
-                self.manage_neuron(
-                    new_id,
-                    Command::Configure(Configure {
-                        operation: Some(Operation::StartDissolving(StartDissolving {})),
-                    }),
-                )
-                .await?;
-                eprintln!("Started dissolving neuron {}", new_id);
-            }
+        // Splitting twice would withdraw twice as much, so before retrying check whether the
+        // parent's stake has gone down by the amount, and if so look for the neuron it went to: one
+        // which didn't exist before, with the parent's controller and dissolve state, and the stake
+        // the split would have given it.
+        let neurons = self.list_neurons(vec![], ReadMode::Certified).await?;
+        let existing: Vec<u64> = neurons
+            .iter()
+            .filter_map(|n| n.id.as_ref().map(|n| n.id))
+            .collect();
+        let Some(parent) = neurons
+            .into_iter()
+            .find(|n| n.id.as_ref().map(|n| n.id) == Some(neuron_id))
+        else {
+            return Err(OracleError::governance(
+                Some(neuron_id),
+                Error::NotFound(format!("neuron {} to split", neuron_id)),
+            ));
+        };
+        let response = self
+            .retry
+            .retry_verified(
+                "split",
+                || self.manage_neuron(neuron_id, Command::Split(Split { amount_e8s })),
+                || async {
+                    let neurons = self.list_neurons(vec![], ReadMode::Certified).await?;
+                    let split = neurons.iter().any(|n| {
+                        n.id.as_ref().map(|n| n.id) == Some(neuron_id)
+                            && n.cached_neuron_stake_e8s.saturating_add(amount_e8s)
+                                <= parent.cached_neuron_stake_e8s
+                    });
+                    if !split {
+                        return Ok(None);
+                    }
+                    let created: Vec<u64> = neurons
+                        .iter()
+                        .filter(|n| {
+                            n.controller == parent.controller
+                                && n.dissolve_state == parent.dissolve_state
+                                && n.cached_neuron_stake_e8s == amount_e8s.saturating_sub(ICP_FEE)
+                        })
+                        .filter_map(|n| n.id.as_ref().map(|n| n.id))
+                        .filter(|id| !existing.contains(id))
+                        .collect();
+                    match created[..] {
+                        [id] => Ok(Some(manage_neuron_response::Command::Split(SplitResponse {
+                            created_neuron_id: Some(NeuronId { id }),
+                        }))),
+                        // The parent's stake went down, so retrying would split it twice.
+                        _ => Err(OracleError::governance(
+                            Some(neuron_id),
+                            Error::MalformedResponse(format!(
+                                "neuron was split, but couldn't tell which of {:?} is the new \
+                                 neuron",
+                                created
+                            )),
+                        )),
+                    }
+                },
+            )
+            .await?;
+        let manage_neuron_response::Command::Split(SplitResponse {
+            created_neuron_id: Some(NeuronId {
+                id: new_id,
+            }),
+        }) = response else {
+            return Err(OracleError::governance(
+                Some(neuron_id),
+                Error::MalformedResponse("no new neuron id in split response".to_string()),
+            ));
+        };
+        // The split already happened, so failing now would lose the new neuron's id.
+        if let Err(err) = self.approvals.consume(&operation) {
+            warn!(neuron_id, new_id, %err, "Split, but couldn't mark the approval as used");
         }
-        Ok(replacements)
+        metrics::ICP_MOVED_E8S
+            .with_label_values(&["split"])
+            .inc_by(amount_e8s);
+        Ok(new_id)
+    }
+
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn start_dissolving(&self, neuron_id: u64) -> Result<()> {
+        let command = Command::Configure(Configure {
+            operation: Some(Operation::StartDissolving(StartDissolving {})),
+        });
+        // Starting an already-dissolving neuron is an error, so check first before retrying.
+        self.retry
+            .retry_verified(
+                "start dissolving",
+                || self.manage_neuron(neuron_id, command.clone()),
+                || async {
+                    let dissolving = self.get_neuron(neuron_id).await?.map_or(false, |n| {
+                        matches!(
+                            n.dissolve_state,
+                            Some(DissolveState::WhenDissolvedTimestampSeconds(_))
+                        )
+                    });
+                    Ok(dissolving
+                        .then(|| manage_neuron_response::Command::Configure(Default::default())))
+                },
+            )
+            .await?;
+        Ok(())
     }
 
-    async fn claim_neuron(&self, controller: Option<Principal>, memo: u64) -> anyhow::Result<u64> {
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn claim_neuron(&self, controller: Option<Principal>, memo: u64) -> Result<u64> {
+        // Claiming is idempotent, a second claim just refreshes the neuron.
+        let arg = Encode!(&ClaimOrRefreshNeuronFromAccount {
+            controller: controller.map(PrincipalId),
+            memo,
+        })?;
         let response = self
-            .agent
-            .update(&self.canister_id, "claim_or_refresh_neuron_from_account")
-            .with_arg(&Encode!(&ClaimOrRefreshNeuronFromAccount {
-                controller: controller.map(PrincipalId),
-                memo,
-            })?)
-            .call_and_wait()
+            .retry
+            .retry("claim_or_refresh_neuron_from_account", || async {
+                Ok(self
+                    .agent
+                    .update(&self.canister_id, "claim_or_refresh_neuron_from_account")
+                    .with_arg(&arg)
+                    .call_and_wait()
+                    .await?)
+            })
             .await?;
 
-        let result = Decode!(response.as_slice(), ClaimOrRefreshNeuronFromAccountResponse)
-            .map_err(|err| anyhow!(err))?;
+        let result = Decode!(response.as_slice(), ClaimOrRefreshNeuronFromAccountResponse)?;
         let Some(inner) = result.result else {
-            bail!("Unexpected result claiming neuron, memo: {}", memo);
+            return Err(OracleError::governance(
+                None,
+                Error::MalformedResponse(format!("no result claiming neuron, memo: {}", memo)),
+            ));
         };
         match inner {
-            claim_or_refresh_neuron_from_account_response::Result::Error(err) => bail!(
-                "Error claiming neuron, memo: {}, err: {}",
-                memo,
-                err.error_message
-            ),
+            claim_or_refresh_neuron_from_account_response::Result::Error(err) => {
+                Err(OracleError::governance(None, err.into()))
+            }
             claim_or_refresh_neuron_from_account_response::Result::NeuronId(NeuronId { id }) => {
                 Ok(id)
             }
         }
     }
 
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
     async fn increase_neuron_delay(
         &self,
         neuron_id: u64,
         additional_dissolve_delay_seconds: u32,
-    ) -> anyhow::Result<()> {
-        self.manage_neuron(
-            neuron_id,
-            Command::Configure(Configure {
-                operation: Some(Operation::IncreaseDissolveDelay(IncreaseDissolveDelay {
-                    additional_dissolve_delay_seconds,
-                })),
-            }),
-        )
-        .await?;
+    ) -> Result<()> {
+        // Not retried: the delay is additive, and there's no way to tell whether a failed attempt
+        // already increased it.
+        let command = Command::Configure(Configure {
+            operation: Some(Operation::IncreaseDissolveDelay(IncreaseDissolveDelay {
+                additional_dissolve_delay_seconds,
+            })),
+        });
+        retry::Policy::once()
+            .retry("increase dissolve delay", || {
+                self.manage_neuron(neuron_id, command.clone())
+            })
+            .await?;
         Ok(())
     }
 
-    async fn add_hotkey(&self, neuron_id: u64, key: Principal) -> anyhow::Result<()> {
-        self.manage_neuron(
-            neuron_id,
-            Command::Configure(Configure {
-                operation: Some(Operation::AddHotKey(AddHotKey {
-                    new_hot_key: Some(key).map(PrincipalId),
-                })),
-            }),
-        )
-        .await?;
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn add_hotkey(&self, neuron_id: u64, key: Principal) -> Result<()> {
+        let command = Command::Configure(Configure {
+            operation: Some(Operation::AddHotKey(AddHotKey {
+                new_hot_key: Some(key).map(PrincipalId),
+            })),
+        });
+        // Adding a duplicate hotkey is an error, so check first before retrying.
+        self.retry
+            .retry_verified(
+                "add hotkey",
+                || self.manage_neuron(neuron_id, command.clone()),
+                || async {
+                    let added = self
+                        .get_neuron(neuron_id)
+                        .await?
+                        .map_or(false, |n| n.hot_keys.contains(&PrincipalId(key)));
+                    Ok(added
+                        .then(|| manage_neuron_response::Command::Configure(Default::default())))
+                },
+            )
+            .await?;
         Ok(())
     }
 
-    async fn enable_auto_merge_maturity(&self, neuron_id: u64) -> anyhow::Result<()> {
-        self.manage_neuron(
-            neuron_id,
-            Command::Configure(Configure {
-                operation: Some(Operation::ChangeAutoStakeMaturity(
-                    ChangeAutoStakeMaturity {
-                        requested_setting_for_auto_stake_maturity: true,
-                    },
-                )),
-            }),
-        )
-        .await?;
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn enable_auto_merge_maturity(&self, neuron_id: u64) -> Result<()> {
+        let command = Command::Configure(Configure {
+            operation: Some(Operation::ChangeAutoStakeMaturity(
+                ChangeAutoStakeMaturity {
+                    requested_setting_for_auto_stake_maturity: true,
+                },
+            )),
+        });
+        // Setting the flag twice has no further effect, so this is always safe to retry.
+        self.retry
+            .retry("enable auto stake maturity", || {
+                self.manage_neuron(neuron_id, command.clone())
+            })
+            .await?;
         Ok(())
     }
 
-    fn account_id(&self) -> anyhow::Result<AccountIdentifier> {
+    #[instrument(skip(self, neuron_ids), fields(canister = %self.canister_id), err)]
+    async fn list_neurons(&self, neuron_ids: Vec<u64>, mode: ReadMode) -> Result<Vec<Neuron>> {
+        let arg = Encode!(&ListNeurons {
+            neuron_ids,
+            include_neurons_readable_by_caller: true,
+        })?;
+        let response = self
+            .retry
+            .retry("list_neurons", || {
+                query::read(self.agent, &self.canister_id, "list_neurons", &arg, mode)
+            })
+            .await?;
+
+        let result = Decode!(response.as_slice(), ListNeuronsResponse)?;
+        Ok(result.full_neurons)
+    }
+
+    fn account_id(&self) -> Result<AccountIdentifier> {
         PrincipalId::try_from(self.canister_id.as_slice())
             .map(|p| AccountIdentifier::new(p, None))
-            .map_err(|err| anyhow!(err))
+            .map_err(|err| OracleError::Config(err.to_string()))
+    }
+
+    #[instrument(skip(self), err)]
+    fn check_approval(&self, operation: &approval::Operation) -> Result<()> {
+        self.approvals.check(operation)
     }
 }
@@ -0,0 +1,153 @@
+use candid::Principal;
+use clap::Args;
+use ic_base_types::PrincipalId;
+use ic_nns_governance::pb::v1::{neuron::DissolveState, Neuron};
+use icp_ledger::AccountIdentifier;
+use serde::Serialize;
+use std::fmt;
+
+use super::ICP_FEE;
+use crate::error::{OracleError, Result};
+
+#[derive(Args, Debug, Clone)]
+pub struct DisburseArgs {
+    /// Account (hex) which neurons may be disbursed to, besides the deposits canister. May be
+    /// repeated
+    #[arg(
+        long = "disburse-allow",
+        env = "ORACLE_DISBURSE_ALLOW",
+        value_delimiter = ','
+    )]
+    pub disburse_allow: Vec<String>,
+
+    /// Refuse to disburse a neuron with more stake than this
+    #[arg(long, env = "ORACLE_DISBURSE_MAX_E8S")]
+    pub disburse_max_e8s: Option<u64>,
+}
+
+impl DisburseArgs {
+    pub fn safety(&self, deposits_address: AccountIdentifier) -> Result<DisburseSafety> {
+        let mut allowed = vec![deposits_address];
+        for hex in self.disburse_allow.iter() {
+            allowed.push(AccountIdentifier::from_hex(hex).map_err(|e| {
+                OracleError::Config(format!("Bad --disburse-allow account {hex}: {e}"))
+            })?);
+        }
+        Ok(DisburseSafety {
+            allowed,
+            max_e8s: self.disburse_max_e8s,
+        })
+    }
+}
+
+// Why a disburse was refused.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Refusal {
+    DestinationNotAllowed {
+        to: String,
+    },
+    NeuronNotFound,
+    NotController {
+        controller: Option<String>,
+        expected: String,
+    },
+    NotDissolved {
+        dissolve_state: String,
+    },
+    AmountOutOfBounds {
+        stake_e8s: u64,
+        min_e8s: u64,
+        max_e8s: Option<u64>,
+    },
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::DestinationNotAllowed { to } => {
+                write!(f, "destination {} is not allowed", to)
+            }
+            Refusal::NeuronNotFound => write!(f, "neuron not found"),
+            Refusal::NotController {
+                controller,
+                expected,
+            } => write!(
+                f,
+                "neuron is controlled by {}, not {}",
+                controller.as_deref().unwrap_or("nobody"),
+                expected
+            ),
+            Refusal::NotDissolved { dissolve_state } => {
+                write!(f, "neuron is not dissolved: {}", dissolve_state)
+            }
+            Refusal::AmountOutOfBounds {
+                stake_e8s,
+                min_e8s,
+                max_e8s,
+            } => write!(
+                f,
+                "stake {} e8s is outside of bounds {}..{}",
+                stake_e8s,
+                min_e8s,
+                max_e8s.map_or("".to_string(), |m| m.to_string())
+            ),
+        }
+    }
+}
+
+// Checks a neuron is safe to disburse before the command is sent.
+#[derive(Debug, Clone, Default)]
+pub struct DisburseSafety {
+    // Accounts neurons may be disbursed to.
+    pub allowed: Vec<AccountIdentifier>,
+    pub max_e8s: Option<u64>,
+}
+
+impl DisburseSafety {
+    // Check the neuron, freshly read from governance, may be disbursed to `to` by `controller`.
+    // Returns false if it has already been disbursed, so there is nothing left to do.
+    pub fn check(
+        &self,
+        neuron: Option<&Neuron>,
+        to: &AccountIdentifier,
+        controller: Principal,
+        now: u64,
+    ) -> std::result::Result<bool, Refusal> {
+        if !self.allowed.contains(to) {
+            return Err(Refusal::DestinationNotAllowed { to: to.to_hex() });
+        }
+        let neuron = neuron.ok_or(Refusal::NeuronNotFound)?;
+        if neuron.controller != Some(PrincipalId(controller)) {
+            return Err(Refusal::NotController {
+                controller: neuron.controller.map(|c| c.to_string()),
+                expected: controller.to_string(),
+            });
+        }
+        let dissolved = match neuron.dissolve_state {
+            Some(DissolveState::WhenDissolvedTimestampSeconds(ts)) => ts <= now,
+            Some(DissolveState::DissolveDelaySeconds(delay)) => delay == 0,
+            None => false,
+        };
+        if !dissolved {
+            return Err(Refusal::NotDissolved {
+                dissolve_state: format!("{:?}", neuron.dissolve_state),
+            });
+        }
+
+        let stake_e8s = neuron
+            .cached_neuron_stake_e8s
+            .saturating_sub(neuron.neuron_fees_e8s);
+        if stake_e8s == 0 {
+            return Ok(false);
+        }
+        if stake_e8s <= ICP_FEE || self.max_e8s.map_or(false, |max| stake_e8s > max) {
+            return Err(Refusal::AmountOutOfBounds {
+                stake_e8s,
+                min_e8s: ICP_FEE + 1,
+                max_e8s: self.max_e8s,
+            });
+        }
+        Ok(true)
+    }
+}
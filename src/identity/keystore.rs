@@ -0,0 +1,124 @@
+use anyhow::{anyhow, bail, Context};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::{fmt, io::Read, mem::ManuallyDrop, os::unix::io::FromRawFd};
+
+const VERSION: u32 = 1;
+
+// scrypt cost parameters for new keystores. log_n = 17 takes ~128MiB and about a second, which is
+// fine for something decrypted once per run.
+const SCRYPT_LOG_N: u8 = 17;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+// A PEM private key, encrypted with XChaCha20-Poly1305 under a key derived from a passphrase with
+// scrypt. Stored as JSON.
+#[derive(Serialize, Deserialize)]
+struct Keystore {
+    version: u32,
+    kdf: String,
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+// A passphrase, which is never printed.
+#[derive(Clone)]
+pub struct Secret(pub(super) String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+// Read the passphrase from the given file descriptor if set, otherwise from the environment
+// variable. A trailing newline is ignored.
+pub fn read_passphrase(env: &str, fd: Option<i32>) -> anyhow::Result<Secret> {
+    let mut passphrase = match fd {
+        Some(fd) => {
+            if fd < 0 {
+                bail!("Bad passphrase fd {}", fd);
+            }
+            // Safety: the fd is handed to us by the caller to read from. It's borrowed, never
+            // closed, as it may be stdin or an fd something else in the process also uses.
+            let mut file = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+            let mut buffer = String::new();
+            file.read_to_string(&mut buffer)
+                .with_context(|| format!("Couldn't read passphrase from fd {}", fd))?;
+            buffer
+        }
+        None => std::env::var(env)
+            .with_context(|| format!("Couldn't read passphrase from ${}", env))?,
+    };
+    while passphrase.ends_with('\n') || passphrase.ends_with('\r') {
+        passphrase.pop();
+    }
+    if passphrase.is_empty() {
+        bail!("Keystore passphrase is empty");
+    }
+    Ok(Secret(passphrase))
+}
+
+fn derive_key(passphrase: &Secret, salt: &[u8], log_n: u8, r: u32, p: u32) -> anyhow::Result<Key> {
+    let params = scrypt::Params::new(log_n, r, p, 32).map_err(|e| anyhow!(e))?;
+    let mut key = Key::default();
+    scrypt::scrypt(passphrase.0.as_bytes(), salt, &params, &mut key).map_err(|e| anyhow!(e))?;
+    Ok(key)
+}
+
+// Encrypt a PEM private key, returning the keystore file contents.
+pub fn encrypt(pem: &str, passphrase: &Secret) -> anyhow::Result<String> {
+    let mut salt = [0u8; 32];
+    let mut nonce = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let key = derive_key(passphrase, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?;
+    let ciphertext = XChaCha20Poly1305::new(&key)
+        .encrypt(XNonce::from_slice(&nonce), pem.as_bytes())
+        .map_err(|_| anyhow!("Couldn't encrypt keystore"))?;
+
+    Ok(serde_json::to_string_pretty(&Keystore {
+        version: VERSION,
+        kdf: "scrypt".to_string(),
+        log_n: SCRYPT_LOG_N,
+        r: SCRYPT_R,
+        p: SCRYPT_P,
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    })?)
+}
+
+// Decrypt keystore file contents, returning the PEM private key. The key only ever exists in
+// memory.
+pub fn decrypt(contents: &str, passphrase: &Secret) -> anyhow::Result<String> {
+    let keystore: Keystore = serde_json::from_str(contents).context("Couldn't parse keystore")?;
+    if keystore.version != VERSION || keystore.kdf != "scrypt" {
+        bail!(
+            "Unsupported keystore version {}, kdf {}",
+            keystore.version,
+            keystore.kdf
+        );
+    }
+    let salt = hex::decode(&keystore.salt).context("Malformed keystore salt")?;
+    let nonce = hex::decode(&keystore.nonce).context("Malformed keystore nonce")?;
+    let ciphertext = hex::decode(&keystore.ciphertext).context("Malformed keystore ciphertext")?;
+    if nonce.len() != 24 {
+        bail!("Malformed keystore nonce, len: {}, expected 24", nonce.len());
+    }
+
+    let key = derive_key(passphrase, &salt, keystore.log_n, keystore.r, keystore.p)?;
+    let pem = XChaCha20Poly1305::new(&key)
+        .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| anyhow!("Couldn't decrypt keystore, wrong passphrase?"))?;
+    String::from_utf8(pem).context("Keystore does not contain a PEM key")
+}
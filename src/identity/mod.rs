@@ -1,24 +1,32 @@
-use anyhow::{anyhow, Context};
+use anyhow::Context;
 use candid::Principal;
-use clap::Args;
+use clap::{Args, ValueEnum};
 use ic_agent::{
     Agent,
     identity::{AnonymousIdentity, BasicIdentity, Secp256k1Identity},
     Identity,
 };
+use ic_identity_hsm::HardwareIdentity;
 use ic_base_types::PrincipalId;
 use icp_ledger::AccountIdentifier;
+use once_cell::sync::OnceCell;
 use std::{
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, Mutex},
     path::{Path, PathBuf},
-    time::Duration,
 };
-use tokio::runtime::Handle;
 
-mod canister_identity;
+use crate::approval;
+use crate::error::OracleError;
+use crate::governance;
+use crate::retry;
+
+pub mod keystore;
+pub mod network;
+mod remote_identity;
+mod remote_signer;
+mod signer;
 
-const DEFAULT_IC_URL: &str = "https://icp0.io";
 const DEFAULT_DEPOSITS_CANISTER_ID: &str = "hnwvc-lyaaa-aaaal-aaf6q-cai";
 const DEFAULT_GOVERNANCE_CANISTER_ID: &str = "rrkah-fqaaa-aaaaa-aaaaq-cai";
 
@@ -28,124 +36,409 @@ pub struct IdentityArgs {
     #[arg(long)]
     pub private_pem: Option<PathBuf>,
 
-    // Principal of the ECDSA signing canister
+    /// Encrypted keystore file to use for the identity, instead of a plaintext PEM. Create one
+    /// with `oracle encrypt-key`
+    #[arg(long, env = "ORACLE_KEYSTORE", conflicts_with = "private_pem")]
+    pub keystore: Option<PathBuf>,
+
+    /// Environment variable to read the keystore passphrase from
+    #[arg(long, default_value = "ORACLE_KEYSTORE_PASSPHRASE")]
+    pub keystore_passphrase_env: String,
+
+    /// File descriptor to read the keystore passphrase from, instead of an environment variable
     #[arg(long)]
-    pub signing_canister: String,
+    pub keystore_passphrase_fd: Option<i32>,
+
+    /// PKCS#11 library to use an HSM-held key for the identity, e.g.
+    /// /usr/lib/softhsm/libsofthsm2.so
+    #[arg(
+        long,
+        env = "ORACLE_HSM_PKCS11_LIB",
+        conflicts_with_all = ["private_pem", "keystore"],
+        requires = "hsm_key_id"
+    )]
+    pub hsm_pkcs11_lib: Option<PathBuf>,
+
+    /// Slot index of the HSM key
+    #[arg(long, env = "ORACLE_HSM_SLOT_INDEX", default_value = "0")]
+    pub hsm_slot_index: usize,
+
+    /// Id of the HSM key, in hex
+    #[arg(long, env = "ORACLE_HSM_KEY_ID")]
+    pub hsm_key_id: Option<String>,
+
+    /// Environment variable to read the HSM user PIN from
+    #[arg(long, default_value = "ORACLE_HSM_PIN")]
+    pub hsm_pin_env: String,
+
+    /// Which kind of remote signer holds the key the oracle acts as
+    #[arg(long, env = "ORACLE_SIGNER", value_enum, default_value = "canister")]
+    pub signer: SignerKind,
+
+    // Principal of the ECDSA signing canister, or of the proxy canister with --signer ecdsa-proxy.
+    // Only checked for once it's needed, as it may come from the config file.
+    #[arg(long, env = "ORACLE_SIGNING_CANISTER")]
+    pub signing_canister: Option<String>,
+
+    /// Name of the threshold ECDSA key, with --signer ecdsa-proxy
+    #[arg(long, env = "ORACLE_ECDSA_KEY_NAME", default_value = "key_1")]
+    pub ecdsa_key_name: String,
+
+    /// Hex-encoded derivation path segment, with --signer ecdsa-proxy. May be repeated
+    #[arg(long, env = "ORACLE_ECDSA_DERIVATION_PATH", value_delimiter = ',')]
+    pub ecdsa_derivation_path: Vec<String>,
+
+    /// Url of the signer service, with --signer http
+    #[arg(long, env = "ORACLE_SIGNER_URL")]
+    pub signer_url: Option<String>,
+
+    /// Environment variable to read a bearer token for the signer service from, if set
+    #[arg(long, default_value = "ORACLE_SIGNER_TOKEN")]
+    pub signer_token_env: String,
 
     /// Principal of the deposits canister
-    #[arg(long, default_value = DEFAULT_DEPOSITS_CANISTER_ID)]
+    #[arg(long, env = "ORACLE_DEPOSITS_CANISTER", default_value = DEFAULT_DEPOSITS_CANISTER_ID)]
     pub deposits_canister: String,
 
     /// Principal of the governance canister
-    #[arg(long, default_value = DEFAULT_GOVERNANCE_CANISTER_ID)]
+    #[arg(long, env = "ORACLE_GOVERNANCE", default_value = DEFAULT_GOVERNANCE_CANISTER_ID)]
     pub governance: String,
 
-    /// Url of the IC replica
-    #[arg(long, env = "IC_URL", default_value = DEFAULT_IC_URL)]
-    pub ic_url: String,
+    /// Which network the replica is on. On mainnet the root key is never fetched from the
+    /// replica, so certificates are always checked against the real root of trust
+    #[arg(long, env = "ORACLE_NETWORK", value_enum, default_value = "mainnet")]
+    pub network: network::Network,
+
+    /// Url of the IC replica. Defaults to https://icp0.io on mainnet, and http://127.0.0.1:4943
+    /// on a local network
+    #[arg(long, env = "IC_URL")]
+    pub ic_url: Option<String>,
+
+    /// Root key to verify certificates against, as hex, or a file holding it as DER or hex.
+    /// Required with --network custom, and not allowed with --network mainnet
+    #[arg(long, env = "ORACLE_ROOT_KEY")]
+    pub root_key: Option<String>,
+
+    /// Directory to cache the remote signer's public key in, so it only needs to be fetched
+    /// once
+    #[arg(long, env = "ORACLE_SIGNER_KEY_CACHE")]
+    pub signer_key_cache: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub retry: retry::RetryArgs,
+
+    #[command(flatten)]
+    pub approval: approval::ApprovalArgs,
+
+    #[command(flatten)]
+    pub disburse: governance::DisburseArgs,
+
+    // The local key source is read at most once, as a passphrase fd or STDIN can't be re-read.
+    #[arg(skip)]
+    local_auth: Mutex<Option<AuthInfo>>,
+
+    // Each identity is only loaded once, as decrypting a keystore runs scrypt, and an HSM key opens
+    // a new session.
+    #[arg(skip)]
+    local_identity: OnceCell<Arc<dyn Identity>>,
+    #[arg(skip)]
+    identity: OnceCell<Arc<dyn Identity>>,
 }
 
 impl IdentityArgs {
-    pub fn should_fetch_root_key(&self) -> bool {
-        self.ic_url != DEFAULT_IC_URL
+    // Where to reach the IC, and which root key to trust.
+    pub fn endpoint(&self) -> anyhow::Result<network::Endpoint> {
+        Ok(network::Endpoint::new(
+            self.network,
+            self.ic_url.as_deref(),
+            self.root_key.as_deref(),
+        )?)
+    }
+
+    // Which governance operations need operator approval. Disbursing to the deposits canister
+    // never does.
+    pub fn approval_policy(&self) -> anyhow::Result<approval::Policy> {
+        Ok(self.approval.policy(self.deposits_address()?)?)
+    }
+
+    // Which accounts neurons may be disbursed to, and the other disburse safety checks.
+    pub fn disburse_safety(&self) -> anyhow::Result<governance::DisburseSafety> {
+        Ok(self.disburse.safety(self.deposits_address()?)?)
+    }
+
+    fn deposits_address(&self) -> anyhow::Result<AccountIdentifier> {
+        let deposits = Principal::from_text(&self.deposits_canister)?;
+        Ok(AccountIdentifier::new(PrincipalId(deposits), None))
     }
 
     pub async fn create_agent(&self) -> anyhow::Result<Agent> {
-        self.create_agent_for_auth(self.get_auth()?).await
+        self.create_agent_for_identity(self.identity()?).await
     }
 
     // Create an agent with no canister signing, only the local key
     pub async fn create_local_agent(&self) -> anyhow::Result<Agent> {
-        self.create_agent_for_auth(match &self.private_pem {
-            Some(pem_file) => AuthInfo::PemFile(read_file(pem_file, "PEM")?),
-            None => AuthInfo::NoAuth,
-        }).await
+        self.create_agent_for_identity(self.local_identity()?).await
     }
 
-    async fn create_agent_for_auth(&self, auth: AuthInfo) -> anyhow::Result<Agent> {
-        let timeout = Duration::from_secs(60 * 5);
-        let identity = get_identity(&auth)?;
+    // The identity the oracle acts as, signing with the remote signer.
+    fn identity(&self) -> anyhow::Result<Arc<dyn Identity>> {
+        self.identity
+            .get_or_try_init(|| Ok(Arc::from(get_identity(&self.get_auth()?)?)))
+            .cloned()
+    }
+
+    // The local key's identity, used to call the remote signer.
+    fn local_identity(&self) -> anyhow::Result<Arc<dyn Identity>> {
+        self.local_identity
+            .get_or_try_init(|| Ok(Arc::from(get_identity(&self.get_local_auth()?)?)))
+            .cloned()
+    }
+
+    // The local key, from whichever of the PEM, keystore or HSM options was given.
+    fn get_local_auth(&self) -> anyhow::Result<AuthInfo> {
+        let mut cached = self.local_auth.lock().unwrap();
+        if let Some(auth) = cached.as_ref() {
+            return Ok(auth.clone());
+        }
+        let auth = if let Some(pem_file) = &self.private_pem {
+            AuthInfo::PemFile(read_file(pem_file, "PEM")?)
+        } else if let Some(keystore) = &self.keystore {
+            let passphrase = keystore::read_passphrase(
+                &self.keystore_passphrase_env,
+                self.keystore_passphrase_fd,
+            )?;
+            AuthInfo::Keystore(KeystoreInfo {
+                contents: read_file(keystore, "keystore")?,
+                passphrase,
+            })
+        } else if let Some(pkcs11_lib) = &self.hsm_pkcs11_lib {
+            AuthInfo::Hsm(HsmInfo {
+                pkcs11_lib: pkcs11_lib.clone(),
+                slot_index: self.hsm_slot_index,
+                key_id: self.hsm_key_id.clone().unwrap_or_default(),
+                pin_env: self.hsm_pin_env.clone(),
+            })
+        } else {
+            AuthInfo::NoAuth
+        };
+        *cached = Some(auth.clone());
+        Ok(auth)
+    }
+
+    async fn create_agent_for_identity(
+        &self,
+        identity: Arc<dyn Identity>,
+    ) -> anyhow::Result<Agent> {
+        let endpoint = self.endpoint()?;
         let agent = Agent::builder()
             .with_transport(
-                ic_agent::agent::http_transport::ReqwestHttpReplicaV2Transport::create(&self.ic_url)?,
+                ic_agent::agent::http_transport::ReqwestHttpReplicaV2Transport::create(&endpoint.url)
+                    .map_err(OracleError::from)?,
             )
-            .with_ingress_expiry(Some(timeout))
-            .with_boxed_identity(identity)
+            .with_ingress_expiry(Some(retry::INGRESS_EXPIRY))
+            .with_arc_identity(identity)
             .build()
-            .map_err(|err| anyhow!(err))?;
+            .map_err(OracleError::from)?;
 
-        if self.should_fetch_root_key() {
-            // Not on the main net, we need to fetch the root key.
-            agent.fetch_root_key().await?;
-        }
+        endpoint.trust(&agent).await?;
 
         Ok(agent)
     }
 
     fn get_auth(&self) -> anyhow::Result<AuthInfo> {
-        let handle = Handle::try_current().map_err(|e| anyhow!(e))?;
-        // Get PEM from the file if provided, or try to convert from the seed file
-        let local = match &self.private_pem {
-            Some(pem_file) => AuthInfo::PemFile(read_file(pem_file, "PEM")?),
-            None => AuthInfo::NoAuth,
-        };
-        // Wrap this in a canister-signer
-        Ok(AuthInfo::Canister(CanisterInfo {
-            fetch_root_key: self.should_fetch_root_key(),
-            handle,
-            ic_url: self.ic_url.clone(),
-            local: Arc::from(get_identity(&local)?),
-            signer: Principal::from_text(&self.signing_canister)?,
+        // Wrap the local key in a remote signer
+        Ok(AuthInfo::Remote(RemoteInfo {
+            endpoint: self.endpoint()?,
+            key_cache: self.signer_key_cache.clone(),
+            local: self.local_identity()?,
+            retry: self.retry.policy(),
+            signer: self.signer_info()?,
         }))
     }
 
+    fn signer_info(&self) -> anyhow::Result<SignerInfo> {
+        let signing_canister = || -> anyhow::Result<Principal> {
+            let canister = self.signing_canister.as_ref().ok_or_else(|| {
+                OracleError::Config(format!(
+                    "--signing-canister is required with --signer {:?}",
+                    self.signer
+                ))
+            })?;
+            Ok(Principal::from_text(canister)?)
+        };
+        Ok(match self.signer {
+            SignerKind::Canister => SignerInfo::Canister(signing_canister()?),
+            SignerKind::EcdsaProxy => SignerInfo::EcdsaProxy {
+                canister: signing_canister()?,
+                key_name: self.ecdsa_key_name.clone(),
+                derivation_path: self
+                    .ecdsa_derivation_path
+                    .iter()
+                    .map(|segment| {
+                        hex::decode(segment).map_err(|e| {
+                            OracleError::Config(format!(
+                                "Bad derivation path segment {segment}: {e}"
+                            ))
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+            },
+            SignerKind::Http => SignerInfo::Http {
+                url: self.signer_url.clone().ok_or_else(|| {
+                    OracleError::Config("--signer-url is required with --signer http".to_string())
+                })?,
+                token: std::env::var(&self.signer_token_env)
+                    .ok()
+                    .map(keystore::Secret),
+            },
+        })
+    }
+
     pub async fn principal(&self) -> anyhow::Result<Principal> {
-        let auth = self.get_auth()?;
+        Ok(self.identity()?.sender().map_err(OracleError::Signer)?)
+    }
+
+    // The signer's principal, always fetching its public key from the remote signer rather than
+    // the key cache, to confirm the signer is answering.
+    pub async fn fetch_principal(&self) -> anyhow::Result<Principal> {
+        let mut auth = self.get_auth()?;
+        if let AuthInfo::Remote(info) = &mut auth {
+            info.key_cache = None;
+        }
         let identity = get_identity(&auth)?;
-        identity.sender().map_err(|e| anyhow!(e))
+        Ok(identity.sender().map_err(OracleError::Signer)?)
     }
 }
 
 
-#[derive(Debug)]
-pub struct CanisterInfo {
-    pub fetch_root_key: bool,
-    pub handle: tokio::runtime::Handle,
-    pub ic_url: String,
+#[derive(Clone, Debug)]
+pub struct KeystoreInfo {
+    pub contents: String,
+    pub passphrase: keystore::Secret,
+}
+
+#[derive(Clone, Debug)]
+pub struct HsmInfo {
+    pub pkcs11_lib: PathBuf,
+    pub slot_index: usize,
+    pub key_id: String,
+    pub pin_env: String,
+}
+
+// Which kind of service holds the oracle's key.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum SignerKind {
+    /// The ECDSA signing canister
+    Canister,
+    /// The management canister's threshold ECDSA, via a proxy canister
+    EcdsaProxy,
+    /// A signer service over HTTP
+    Http,
+}
+
+#[derive(Clone, Debug)]
+pub enum SignerInfo {
+    Canister(Principal),
+    EcdsaProxy {
+        canister: Principal,
+        key_name: String,
+        derivation_path: Vec<Vec<u8>>,
+    },
+    Http {
+        url: String,
+        token: Option<keystore::Secret>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct RemoteInfo {
+    pub endpoint: network::Endpoint,
+    pub key_cache: Option<PathBuf>,
     pub local: Arc<dyn Identity>,
-    pub signer: Principal,
+    pub retry: retry::Policy,
+    pub signer: SignerInfo,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum AuthInfo {
     NoAuth, // No authentication details were provided;
     // only unsigned queries are allowed.
-    PemFile(String),        // --private-pem file specified
-    Canister(CanisterInfo), // --canister-signer principal specified
+    PemFile(String),          // --private-pem file specified
+    Keystore(KeystoreInfo),   // --keystore file specified
+    Hsm(HsmInfo),             // --hsm-pkcs11-lib specified
+    Remote(RemoteInfo),       // --signer specified
 }
 
 /// Returns an identity derived from the private key.
 pub fn get_identity(auth: &AuthInfo) -> anyhow::Result<Box<dyn Identity>> {
     match auth {
         AuthInfo::NoAuth => Ok(Box::new(AnonymousIdentity) as _),
-        AuthInfo::PemFile(pem) => match Secp256k1Identity::from_pem(pem.as_bytes()) {
+        AuthInfo::PemFile(pem) => identity_from_pem(pem),
+        AuthInfo::Keystore(info) => {
+            identity_from_pem(&keystore::decrypt(&info.contents, &info.passphrase)?)
+        }
+        AuthInfo::Hsm(info) => {
+            let pin_env = info.pin_env.clone();
+            let identity = HardwareIdentity::new(
+                &info.pkcs11_lib,
+                info.slot_index,
+                &info.key_id,
+                move || {
+                    std::env::var(&pin_env)
+                        .map_err(|e| format!("Couldn't read HSM PIN from ${}: {}", pin_env, e))
+                },
+            )
+            .context("couldn't load identity from HSM")?;
+            Ok(Box::new(identity) as _)
+        }
+        AuthInfo::Remote(info) => Ok(Box::new(remote_identity::RemoteIdentity::new(
+            get_remote_signer(info)?,
+            info.key_cache.clone(),
+        ))),
+    }
+}
+
+fn get_remote_signer(info: &RemoteInfo) -> anyhow::Result<Box<dyn remote_signer::RemoteSigner>> {
+    let caller = |canister| {
+        signer::CanisterCaller::new(
+            canister,
+            info.local.clone(),
+            info.endpoint.clone(),
+            info.retry,
+        )
+    };
+    Ok(match &info.signer {
+        SignerInfo::Canister(canister) => {
+            Box::new(remote_signer::SigningCanister::new(caller(*canister)?))
+        }
+        SignerInfo::EcdsaProxy {
+            canister,
+            key_name,
+            derivation_path,
+        } => Box::new(remote_signer::EcdsaProxy::new(
+            caller(*canister)?,
+            key_name.clone(),
+            derivation_path.clone(),
+        )),
+        SignerInfo::Http { url, token } => Box::new(remote_signer::HttpSigner::new(
+            url.clone(),
+            token.clone(),
+            info.retry,
+        )?),
+    })
+}
+
+pub fn identity_from_pem(pem: &str) -> anyhow::Result<Box<dyn Identity>> {
+    match Secp256k1Identity::from_pem(pem.as_bytes()) {
+        Ok(id) => Ok(Box::new(id) as _),
+        Err(_) => match BasicIdentity::from_pem(pem.as_bytes()) {
             Ok(id) => Ok(Box::new(id) as _),
-            Err(_) => match BasicIdentity::from_pem(pem.as_bytes()) {
-                Ok(id) => Ok(Box::new(id) as _),
-                Err(e) => Err(e).context("couldn't load identity from PEM file"),
-            },
+            Err(e) => Err(e).context("couldn't load identity from PEM file"),
         },
-        AuthInfo::Canister(info) => Ok(Box::new(canister_identity::CanisterIdentity::new(
-            info.signer,
-            info.local.clone(),
-            info.ic_url.clone(),
-            info.fetch_root_key,
-            info.handle.clone(),
-        ))),
     }
 }
 
-fn read_file(path: impl AsRef<Path>, name: &str) -> anyhow::Result<String> {
+pub fn read_file(path: impl AsRef<Path>, name: &str) -> anyhow::Result<String> {
     let path = path.as_ref();
     if path == Path::new("-") {
         // read from STDIN
@@ -0,0 +1,111 @@
+use clap::ValueEnum;
+use ic_agent::Agent;
+use std::{fs, path::Path};
+
+use crate::error::{OracleError, Result};
+
+const MAINNET_URL: &str = "https://icp0.io";
+const LOCAL_URL: &str = "http://127.0.0.1:4943";
+
+// DER-encoded BLS12-381 public keys, as the IC uses for its root key, are always this long.
+const ROOT_KEY_LEN: usize = 133;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Network {
+    /// The IC mainnet, via any boundary node. Certificates are checked against the mainnet root
+    /// key built into the agent, which is never fetched
+    Mainnet,
+    /// A local replica, e.g. from dfx. Its root key is fetched from the replica, unless pinned
+    Local,
+    /// Any other network, e.g. a testnet. Needs its root key pinned with --root-key
+    Custom,
+}
+
+// How the agent learns the root key it verifies certificates against.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RootKey {
+    // The mainnet root key, which the agent has built in.
+    Mainnet,
+    // Asked for from the replica itself. Only safe for a local replica, as a malicious replica
+    // could hand over a key of its own, and then certify anything.
+    Fetch,
+    // A DER-encoded key given up front.
+    Pinned(Vec<u8>),
+}
+
+// Where to reach the IC, and how to trust its responses.
+#[derive(Clone, Debug)]
+pub struct Endpoint {
+    pub url: String,
+    pub root_key: RootKey,
+}
+
+impl Endpoint {
+    pub fn new(network: Network, url: Option<&str>, root_key: Option<&str>) -> Result<Self> {
+        let root_key = root_key.map(read_root_key).transpose()?;
+        let (url, root_key) = match network {
+            Network::Mainnet => {
+                // Pinning a key here could only ever swap the agent's own mainnet key for another.
+                if root_key.is_some() {
+                    return Err(OracleError::Config(
+                        "--root-key can't be used with --network mainnet, which always uses the \
+                         built-in mainnet root key"
+                            .to_string(),
+                    ));
+                }
+                (url.unwrap_or(MAINNET_URL), RootKey::Mainnet)
+            }
+            Network::Local => (
+                url.unwrap_or(LOCAL_URL),
+                root_key.map_or(RootKey::Fetch, RootKey::Pinned),
+            ),
+            Network::Custom => (
+                url.ok_or_else(|| {
+                    OracleError::Config("--ic-url is required with --network custom".to_string())
+                })?,
+                RootKey::Pinned(root_key.ok_or_else(|| {
+                    OracleError::Config("--root-key is required with --network custom".to_string())
+                })?),
+            ),
+        };
+        Ok(Self {
+            url: url.to_string(),
+            root_key,
+        })
+    }
+
+    // Set up the agent to verify certificates against the root key.
+    pub async fn trust(&self, agent: &Agent) -> Result<()> {
+        match &self.root_key {
+            RootKey::Mainnet => Ok(()),
+            RootKey::Fetch => Ok(agent.fetch_root_key().await?),
+            RootKey::Pinned(key) => Ok(agent.set_root_key(key.clone())?),
+        }
+    }
+}
+
+// A root key given as hex, or as a file holding it as hex or DER.
+fn read_root_key(key: &str) -> Result<Vec<u8>> {
+    let bytes = match hex::decode(key.trim()) {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            let contents = fs::read(Path::new(key)).map_err(|e| {
+                OracleError::Config(format!(
+                    "--root-key is neither hex nor a readable file: {e}"
+                ))
+            })?;
+            match std::str::from_utf8(&contents).map(|s| hex::decode(s.trim())) {
+                Ok(Ok(bytes)) => bytes,
+                _ => contents,
+            }
+        }
+    };
+    if bytes.len() != ROOT_KEY_LEN {
+        return Err(OracleError::Config(format!(
+            "--root-key should be a {} byte DER-encoded key, got {} bytes",
+            ROOT_KEY_LEN,
+            bytes.len()
+        )));
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,119 @@
+use ic_agent::{export::Principal, Identity, Signature};
+use k256::{
+    ecdsa::{self, signature::hazmat::PrehashVerifier, VerifyingKey},
+    pkcs8::EncodePublicKey,
+    sha2::{Digest, Sha256},
+    PublicKey,
+};
+use std::convert::TryInto;
+use std::{fs, path::PathBuf, sync::Mutex};
+
+use super::remote_signer::RemoteSigner;
+use crate::error::{OracleError, Result};
+use crate::file;
+use crate::metrics;
+
+// An identity whose key is held by a remote signer.
+pub struct RemoteIdentity {
+    // Directory to persist the signer's public key in, so later runs don't need to fetch it.
+    pub key_cache: Option<PathBuf>,
+    // The signer's SEC1-encoded public key, once fetched.
+    public_key: Mutex<Option<Vec<u8>>>,
+    signer: Box<dyn RemoteSigner>,
+}
+
+impl RemoteIdentity {
+    pub fn new(signer: Box<dyn RemoteSigner>, key_cache: Option<PathBuf>) -> Self {
+        Self {
+            key_cache,
+            public_key: Mutex::new(None),
+            signer,
+        }
+    }
+
+    fn key_cache_file(&self) -> Option<PathBuf> {
+        self.key_cache
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.pub", self.signer.key_name())))
+    }
+
+    // The signer's SEC1-encoded public key. Fetched from the signer at most once, or loaded from
+    // the key cache if it has been fetched on a previous run.
+    fn sec1_public_key(&self) -> Result<Vec<u8>> {
+        let mut cached = self.public_key.lock().unwrap();
+        if let Some(key) = cached.as_ref() {
+            return Ok(key.clone());
+        }
+
+        let cache_file = self.key_cache_file();
+        let key = match cache_file.as_ref().and_then(|f| fs::read(f).ok()) {
+            Some(key) => key,
+            None => self.signer.public_key()?,
+        };
+        verifying_key(&key)?;
+
+        if let Some(file) = cache_file {
+            if !file.exists() {
+                // Written atomically, as a truncated key would stop every later run from signing.
+                file.parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| file::write_atomic(&file, &key))
+                    .map_err(|e| {
+                        OracleError::Config(format!("Couldn't write {}: {e}", file.display()))
+                    })?;
+            }
+        }
+        *cached = Some(key.clone());
+        Ok(key)
+    }
+
+    fn public_key(&self) -> Result<Vec<u8>> {
+        let public_key: PublicKey = verifying_key(&self.sec1_public_key()?)?.into();
+        let key_der = public_key
+            .to_public_key_der()
+            .map_err(|e| OracleError::Signer(format!("{e}")))?;
+        Ok(key_der.as_bytes().to_vec())
+    }
+}
+
+fn verifying_key(sec1: &[u8]) -> Result<VerifyingKey> {
+    if sec1.len() != 33 {
+        return Err(OracleError::Signer(format!(
+            "malformed public_key, len: {}, expected 33",
+            sec1.len()
+        )));
+    }
+    VerifyingKey::from_sec1_bytes(sec1).map_err(|e| OracleError::Signer(format!("{e}")))
+}
+
+impl Identity for RemoteIdentity {
+    fn sender(&self) -> std::result::Result<Principal, String> {
+        Ok(Principal::self_authenticating(
+            self.public_key().map_err(|e| e.to_string())?,
+        ))
+    }
+
+    fn sign(&self, blob: &[u8]) -> std::result::Result<Signature, String> {
+        let mut hasher = Sha256::new();
+        hasher.update(blob);
+        let message: [u8; 32] = hasher.finalize().as_slice().try_into().unwrap();
+        let timer = metrics::SIGNER_LATENCY.start_timer();
+        let signature_bytes = self.signer.sign(&message).map_err(|e| e.to_string())?;
+        timer.observe_duration();
+
+        // Refuse to use a signature which doesn't match the key we have, in case the signer's key
+        // has changed underneath us.
+        let key = verifying_key(&self.sec1_public_key().map_err(|e| e.to_string())?)
+            .map_err(|e| e.to_string())?;
+        let signature = ecdsa::Signature::try_from(signature_bytes.as_slice())
+            .map_err(|e| format!("malformed signature from remote signer: {e}"))?;
+        key.verify_prehash(&message, &signature).map_err(|_| {
+            "remote signer returned a signature which doesn't match its public key".to_string()
+        })?;
+
+        Ok(Signature {
+            public_key: Some(self.public_key().map_err(|e| e.to_string())?),
+            signature: Some(signature_bytes),
+        })
+    }
+}
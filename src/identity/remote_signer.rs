@@ -0,0 +1,279 @@
+use candid::{CandidType, Principal};
+use k256::sha2::{Digest, Sha256};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::keystore::Secret;
+use super::signer::{CanisterCaller, SignerRuntime};
+use crate::error::{OracleError, Result};
+use crate::retry;
+
+// Holds the oracle's secp256k1 key somewhere off this machine, and signs with it on request.
+//
+// Calls block until the signer responds, as ic-agent's Identity::sign is synchronous.
+pub trait RemoteSigner: Send + Sync {
+    // A stable name for the key, used to name its key cache file.
+    fn key_name(&self) -> String;
+
+    // The SEC1-encoded (compressed) public key.
+    fn public_key(&self) -> Result<Vec<u8>>;
+
+    // Sign a SHA-256 message hash, returning the 64-byte (r, s) signature.
+    fn sign(&self, message_hash: &[u8; 32]) -> Result<Vec<u8>>;
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct PublicKeyArgument {}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct PublicKeyReply {
+    pub public_key: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct SignatureReply {
+    pub signature: Vec<u8>,
+}
+
+// Our own signing canister, with `public_key` and `sign` methods that return
+// `Result<_, String>`.
+pub struct SigningCanister {
+    caller: CanisterCaller,
+}
+
+impl SigningCanister {
+    pub fn new(caller: CanisterCaller) -> Self {
+        Self { caller }
+    }
+
+    fn update<A, R>(&self, method: &str, arg: &A) -> Result<R>
+    where
+        A: CandidType,
+        R: CandidType + DeserializeOwned,
+    {
+        let result: std::result::Result<R, String> = self.caller.update(method, arg)?;
+        result.map_err(OracleError::Signer)
+    }
+}
+
+impl RemoteSigner for SigningCanister {
+    fn key_name(&self) -> String {
+        self.caller.canister().to_string()
+    }
+
+    fn public_key(&self) -> Result<Vec<u8>> {
+        let reply: PublicKeyReply = self.update("public_key", &PublicKeyArgument {})?;
+        Ok(reply.public_key)
+    }
+
+    fn sign(&self, message_hash: &[u8; 32]) -> Result<Vec<u8>> {
+        let reply: SignatureReply = self.update("sign", message_hash)?;
+        Ok(reply.signature)
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum EcdsaCurve {
+    #[serde(rename = "secp256k1")]
+    Secp256k1,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct EcdsaKeyId {
+    curve: EcdsaCurve,
+    name: String,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct EcdsaPublicKeyArgument {
+    canister_id: Option<Principal>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: EcdsaKeyId,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct EcdsaPublicKeyReply {
+    public_key: Vec<u8>,
+    chain_code: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct SignWithEcdsaArgument {
+    message_hash: Vec<u8>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: EcdsaKeyId,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct SignWithEcdsaReply {
+    signature: Vec<u8>,
+}
+
+// A proxy canister which forwards `ecdsa_public_key` and `sign_with_ecdsa` to the IC management
+// canister, with the same candid interface. The key is the proxy's own threshold ECDSA key, as
+// only a canister can call the management canister.
+pub struct EcdsaProxy {
+    caller: CanisterCaller,
+    key_name: String,
+    derivation_path: Vec<Vec<u8>>,
+}
+
+impl EcdsaProxy {
+    pub fn new(caller: CanisterCaller, key_name: String, derivation_path: Vec<Vec<u8>>) -> Self {
+        Self {
+            caller,
+            key_name,
+            derivation_path,
+        }
+    }
+
+    fn key_id(&self) -> EcdsaKeyId {
+        EcdsaKeyId {
+            curve: EcdsaCurve::Secp256k1,
+            name: self.key_name.clone(),
+        }
+    }
+}
+
+impl RemoteSigner for EcdsaProxy {
+    fn key_name(&self) -> String {
+        let mut name = format!("{}-{}", self.caller.canister(), self.key_name);
+        for segment in &self.derivation_path {
+            name.push('-');
+            name.push_str(&hex::encode(segment));
+        }
+        name
+    }
+
+    fn public_key(&self) -> Result<Vec<u8>> {
+        let reply: EcdsaPublicKeyReply = self.caller.update(
+            "ecdsa_public_key",
+            &EcdsaPublicKeyArgument {
+                canister_id: None,
+                derivation_path: self.derivation_path.clone(),
+                key_id: self.key_id(),
+            },
+        )?;
+        Ok(reply.public_key)
+    }
+
+    fn sign(&self, message_hash: &[u8; 32]) -> Result<Vec<u8>> {
+        let reply: SignWithEcdsaReply = self.caller.update(
+            "sign_with_ecdsa",
+            &SignWithEcdsaArgument {
+                message_hash: message_hash.to_vec(),
+                derivation_path: self.derivation_path.clone(),
+                key_id: self.key_id(),
+            },
+        )?;
+        Ok(reply.signature)
+    }
+}
+
+#[derive(Deserialize)]
+struct HttpPublicKeyReply {
+    public_key: String,
+}
+
+#[derive(Serialize)]
+struct HttpSignRequest {
+    message_hash: String,
+}
+
+#[derive(Deserialize)]
+struct HttpSignReply {
+    signature: String,
+}
+
+// A signer service reached over HTTP, e.g. one fronting an HSM on the local network:
+//
+//   GET  <url>/public_key                      -> {"public_key": "<hex SEC1>"}
+//   POST <url>/sign {"message_hash": "<hex>"}  -> {"signature": "<hex r || s>"}
+//
+// If a token is given, it is sent as a bearer token.
+pub struct HttpSigner {
+    url: String,
+    token: Option<Secret>,
+    client: reqwest::Client,
+    retry: retry::Policy,
+    runtime: SignerRuntime,
+}
+
+impl HttpSigner {
+    pub fn new(url: String, token: Option<Secret>, retry: retry::Policy) -> Result<Self> {
+        Ok(Self {
+            url: url.trim_end_matches('/').to_string(),
+            token,
+            client: reqwest::Client::new(),
+            retry,
+            runtime: SignerRuntime::spawn()?,
+        })
+    }
+
+    fn request<T>(&self, method: &'static str, body: Option<Vec<u8>>) -> Result<T>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let url = format!("{}/{}", self.url, method);
+        let client = self.client.clone();
+        let token = self.token.as_ref().map(|t| t.0.clone());
+        let retry = self.retry;
+        let bytes = self.runtime.block_on(async move {
+            retry
+                .retry(method, || async {
+                    let mut request = match &body {
+                        Some(body) => client
+                            .post(&url)
+                            .header("Content-Type", "application/json")
+                            .body(body.clone()),
+                        None => client.get(&url),
+                    };
+                    if let Some(token) = &token {
+                        request = request.bearer_auth(token);
+                    }
+                    let response = request
+                        .send()
+                        .await
+                        .map_err(|e| OracleError::Transport(e.to_string()))?;
+                    let status = response.status();
+                    if status.is_server_error() {
+                        return Err(OracleError::Transport(format!("{url}: {status}")));
+                    }
+                    if !status.is_success() {
+                        return Err(OracleError::Signer(format!("{url}: {status}")));
+                    }
+                    let bytes = response
+                        .bytes()
+                        .await
+                        .map_err(|e| OracleError::Transport(e.to_string()))?;
+                    Ok(bytes.to_vec())
+                })
+                .await
+        })?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| OracleError::Signer(format!("malformed response from {}: {e}", method)))
+    }
+}
+
+impl RemoteSigner for HttpSigner {
+    fn key_name(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.url.as_bytes());
+        format!("http-{}", hex::encode(&hasher.finalize()[..8]))
+    }
+
+    fn public_key(&self) -> Result<Vec<u8>> {
+        let reply: HttpPublicKeyReply = self.request("public_key", None)?;
+        hex::decode(reply.public_key)
+            .map_err(|e| OracleError::Signer(format!("malformed public_key: {e}")))
+    }
+
+    fn sign(&self, message_hash: &[u8; 32]) -> Result<Vec<u8>> {
+        let body = serde_json::to_vec(&HttpSignRequest {
+            message_hash: hex::encode(message_hash),
+        })
+        .map_err(|e| OracleError::Signer(e.to_string()))?;
+        let reply: HttpSignReply = self.request("sign", Some(body))?;
+        hex::decode(reply.signature)
+            .map_err(|e| OracleError::Signer(format!("malformed signature: {e}")))
+    }
+}
@@ -0,0 +1,161 @@
+use candid::{CandidType, Decode, Encode};
+use ic_agent::{export::Principal, Agent, Identity};
+use serde::de::DeserializeOwned;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{mpsc as std_mpsc, Arc},
+    thread,
+};
+use tokio::{
+    runtime::{self, RuntimeFlavor},
+    sync::{mpsc, OnceCell},
+};
+
+use super::network::Endpoint;
+use crate::error::{OracleError, Result};
+use crate::retry;
+
+type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+// Runs calls to a remote signer on a dedicated thread with its own runtime.
+//
+// ic-agent's Identity::sign is synchronous, so whoever asks for a signature has to block until it
+// arrives. Because the remote call never runs on the caller's runtime, blocking can't starve or
+// deadlock it (even a current-thread runtime), and several signatures requested at once are
+// fetched concurrently.
+pub struct SignerRuntime {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+impl SignerRuntime {
+    pub fn spawn() -> Result<Self> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Job>();
+        let rt = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| OracleError::Signer(format!("Couldn't start signer runtime: {e}")))?;
+        thread::Builder::new()
+            .name("signer".to_string())
+            .spawn(move || {
+                rt.block_on(async move {
+                    while let Some(job) = rx.recv().await {
+                        tokio::spawn(job);
+                    }
+                })
+            })
+            .map_err(|e| OracleError::Signer(format!("Couldn't start signer thread: {e}")))?;
+        Ok(Self { jobs: tx })
+    }
+
+    // Run a future on the signer runtime, blocking until it completes.
+    pub fn block_on<T, F>(&self, future: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: Future<Output = Result<T>> + Send + 'static,
+    {
+        let (reply, rx) = std_mpsc::sync_channel(1);
+        self.jobs
+            .send(Box::pin(async move {
+                let _ = reply.send(future.await);
+            }))
+            .map_err(|_| OracleError::Signer("signer thread has stopped".to_string()))?;
+
+        let wait = || {
+            rx.recv()
+                .map_err(|_| OracleError::Signer("signer thread dropped the request".to_string()))?
+        };
+        // On a multi-thread runtime, let the scheduler move other tasks off this worker while we
+        // wait. block_in_place isn't allowed on a current-thread runtime, but there blocking is
+        // still safe, because the signer doesn't need this thread.
+        match runtime::Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(wait)
+            }
+            _ => wait(),
+        }
+    }
+}
+
+// Makes update calls to a signing canister from its own signer runtime, authenticated with the
+// local identity.
+pub struct CanisterCaller {
+    canister: Principal,
+    // Built on first use and reused after that.
+    agent: Arc<OnceCell<Agent>>,
+    identity: Arc<dyn Identity>,
+    endpoint: Endpoint,
+    retry: retry::Policy,
+    runtime: SignerRuntime,
+}
+
+impl CanisterCaller {
+    pub fn new(
+        canister: Principal,
+        identity: Arc<dyn Identity>,
+        endpoint: Endpoint,
+        retry: retry::Policy,
+    ) -> Result<Self> {
+        Ok(Self {
+            canister,
+            agent: Arc::new(OnceCell::new()),
+            identity,
+            endpoint,
+            retry,
+            runtime: SignerRuntime::spawn()?,
+        })
+    }
+
+    pub fn canister(&self) -> Principal {
+        self.canister
+    }
+
+    // Call the canister, blocking until it responds.
+    pub fn update<A, R>(&self, method: &str, arg: &A) -> Result<R>
+    where
+        A: CandidType,
+        R: CandidType + DeserializeOwned,
+    {
+        let arg = Encode!(arg)?;
+        let method = method.to_string();
+        let canister = self.canister;
+        let agent = self.agent.clone();
+        let identity = self.identity.clone();
+        let endpoint = self.endpoint.clone();
+        let retry = self.retry;
+        let bytes = self.runtime.block_on(async move {
+            let agent = agent
+                .get_or_try_init(|| get_agent_async(identity, &endpoint))
+                .await?;
+            // Signing and fetching the public key have no side effects, so are always safe to
+            // retry.
+            retry
+                .retry(&method, || async {
+                    Ok(agent
+                        .update(&canister, &method)
+                        .with_arg(&arg)
+                        .call_and_wait()
+                        .await?)
+                })
+                .await
+        })?;
+        Ok(Decode!(&bytes, R)?)
+    }
+}
+
+fn get_agent(identity: Arc<dyn Identity>, ic_url: &str) -> Result<Agent> {
+    Agent::builder()
+        .with_transport(
+            ic_agent::agent::http_transport::ReqwestHttpReplicaV2Transport::create(ic_url)?,
+        )
+        .with_ingress_expiry(Some(retry::INGRESS_EXPIRY))
+        .with_arc_identity(identity)
+        .build()
+        .map_err(OracleError::from)
+}
+
+async fn get_agent_async(identity: Arc<dyn Identity>, endpoint: &Endpoint) -> Result<Agent> {
+    let agent = get_agent(identity, &endpoint.url)?;
+    endpoint.trust(&agent).await?;
+    Ok(agent)
+}
@@ -0,0 +1,235 @@
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::file;
+
+// Progress of a single neuron split within a daily run. Steps only ever move forward, and each
+// one is persisted before the next update call is made, so an interrupted run can pick up from
+// exactly where it stopped.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum SplitStep {
+    // Returned by the deposits canister, nothing has been sent to governance yet.
+    Planned,
+    // The split call has been sent, but we never saw the response. The neuron may or may not have
+    // been split.
+    Requested,
+    // The split succeeded and created `new_id`.
+    Split { new_id: u64 },
+    // The neuron to be withdrawn (old or new, depending on `should_replace`) is dissolving.
+    Dissolving { new_id: u64 },
+    // The deposits canister has been told to replace the old staking neuron with `new_id`.
+    Replaced { new_id: u64 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Split {
+    pub id: u64,
+    pub amount_e8s: u64,
+    pub should_replace: bool,
+    #[serde(flatten)]
+    pub step: SplitStep,
+}
+
+impl Split {
+    pub fn is_complete(&self) -> bool {
+        match self.step {
+            SplitStep::Replaced { .. } => true,
+            SplitStep::Dissolving { .. } => !self.should_replace,
+            _ => false,
+        }
+    }
+
+    // The neuron the split created, once it's known.
+    pub fn new_id(&self) -> Option<u64> {
+        match self.step {
+            SplitStep::Split { new_id }
+            | SplitStep::Dissolving { new_id }
+            | SplitStep::Replaced { new_id } => Some(new_id),
+            SplitStep::Planned | SplitStep::Requested => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Run {
+    pub started_at: u64,
+    // Neurons which have been disbursed so far in this run.
+    pub disbursed: Vec<u64>,
+    pub disburse_complete: bool,
+    // Set once refreshNeuronsAndApplyInterest has returned, so it is never called twice in a run.
+    pub splits: Option<Vec<Split>>,
+    // Set once the splits have been checked by the strategy, so they are only reported once.
+    #[serde(default = "reviewed_before_strategy")]
+    pub splits_reviewed: bool,
+    pub completed_at: Option<u64>,
+    // When the last run to complete before this one did, so it's still known after a failed run.
+    #[serde(default)]
+    pub previous_completed_at: Option<u64>,
+}
+
+// Runs journaled before splits were reviewed went ahead without a review.
+fn reviewed_before_strategy() -> bool {
+    true
+}
+
+impl Run {
+    fn new(started_at: u64) -> Self {
+        Self {
+            started_at,
+            disbursed: vec![],
+            disburse_complete: false,
+            splits: None,
+            splits_reviewed: false,
+            completed_at: None,
+            previous_completed_at: None,
+        }
+    }
+
+    // When the last successful run, this one or an earlier one, completed.
+    pub fn last_completed_at(&self) -> Option<u64> {
+        self.completed_at.or(self.previous_completed_at)
+    }
+
+    // The step an incomplete run stopped at, and the neurons it was working on.
+    pub fn stopped_at(&self) -> Option<(&'static str, Vec<u64>)> {
+        if self.completed_at.is_some() {
+            return None;
+        }
+        if !self.disburse_complete {
+            return Some(("disburse", vec![]));
+        }
+        let Some(splits) = &self.splits else {
+            return Some(("refresh", vec![]));
+        };
+        if !self.splits_reviewed {
+            return Some(("review", splits.iter().map(|s| s.id).collect()));
+        }
+        let split = splits.iter().find(|s| !s.is_complete())?;
+        Some(match split.step {
+            SplitStep::Planned | SplitStep::Requested => ("split", vec![split.id]),
+            SplitStep::Split { new_id } => ("dissolve", vec![split.id, new_id]),
+            SplitStep::Dissolving { new_id } | SplitStep::Replaced { new_id } => {
+                ("replace", vec![split.id, new_id])
+            }
+        })
+    }
+}
+
+// On-disk record of the daily run. Every mutation is written through to disk before returning.
+pub struct Journal {
+    path: PathBuf,
+    pub run: Run,
+    pub resumed: bool,
+}
+
+impl Journal {
+    // Load the journal at `path`. If the last recorded run did not complete, it is resumed,
+    // otherwise a fresh run is started.
+    pub fn open(path: impl AsRef<Path>, now: u64) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let journal = match Self::last(&path)? {
+            Some(run) if run.completed_at.is_none() => Self {
+                path,
+                run,
+                resumed: true,
+            },
+            last => {
+                let mut run = Run::new(now);
+                run.previous_completed_at = last.and_then(|r| r.last_completed_at());
+                Self {
+                    path,
+                    run,
+                    resumed: false,
+                }
+            }
+        };
+        journal.save()?;
+        Ok(journal)
+    }
+
+    // Read the journal at `path` without modifying it, returning the last run if it did not
+    // complete.
+    pub fn interrupted(path: impl AsRef<Path>) -> anyhow::Result<Option<Run>> {
+        Ok(Self::last(path)?.filter(|r| r.completed_at.is_none()))
+    }
+
+    // Read the journal at `path` without modifying it, returning the last run, complete or not.
+    pub fn last(path: impl AsRef<Path>) -> anyhow::Result<Option<Run>> {
+        let path = path.as_ref();
+        let run = match fs::read_to_string(path) {
+            Ok(s) => serde_json::from_str::<Run>(&s)
+                .with_context(|| format!("Couldn't parse journal {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Couldn't read journal {}", path.display()))
+            }
+        };
+        Ok(Some(run))
+    }
+
+    pub fn record_disbursed(&mut self, id: u64) -> anyhow::Result<()> {
+        self.run.disbursed.push(id);
+        self.save()
+    }
+
+    pub fn record_disburse_complete(&mut self) -> anyhow::Result<()> {
+        self.run.disburse_complete = true;
+        self.save()
+    }
+
+    pub fn record_splits(&mut self, neurons_to_split: &[(u64, u64, bool)]) -> anyhow::Result<()> {
+        if self.run.splits.is_some() {
+            bail!("Splits already recorded for this run");
+        }
+        self.run.splits = Some(
+            neurons_to_split
+                .iter()
+                .map(|(id, amount_e8s, should_replace)| Split {
+                    id: *id,
+                    amount_e8s: *amount_e8s,
+                    should_replace: *should_replace,
+                    step: SplitStep::Planned,
+                })
+                .collect(),
+        );
+        self.run.splits_reviewed = false;
+        self.save()
+    }
+
+    pub fn record_splits_reviewed(&mut self) -> anyhow::Result<()> {
+        if self.run.splits.is_none() {
+            bail!("No splits recorded to review");
+        }
+        self.run.splits_reviewed = true;
+        self.save()
+    }
+
+    pub fn record_split_step(&mut self, index: usize, step: SplitStep) -> anyhow::Result<()> {
+        let Some(split) = self.run.splits.as_mut().and_then(|s| s.get_mut(index)) else {
+            bail!("No split recorded at index {}", index);
+        };
+        split.step = step;
+        self.save()
+    }
+
+    pub fn record_complete(&mut self, now: u64) -> anyhow::Result<()> {
+        self.run.completed_at = Some(now);
+        self.save()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Write to a temp file and rename over the journal, so a crash mid-write never leaves a
+    // truncated journal behind.
+    fn save(&self) -> anyhow::Result<()> {
+        file::write_atomic(&self.path, &serde_json::to_vec_pretty(&self.run)?)
+            .with_context(|| format!("Couldn't write journal {}", self.path.display()))
+    }
+}
@@ -0,0 +1,331 @@
+use async_trait::async_trait;
+use candid::{CandidType, Decode, Encode, Int, Nat, Principal};
+use serde::Deserialize;
+use std::fmt;
+use tracing::instrument;
+
+use crate::error::{OracleError, Result};
+use crate::metrics;
+use crate::query::{self, ReadMode};
+use crate::retry;
+
+// ICRC-1 token ledger, addressed by principal + subaccount instead of AccountIdentifier. Used for
+// the stICP token ledger, and newer ICP ledger flows.
+#[async_trait]
+pub trait Service {
+    async fn balance_of(&self, account: &Account, mode: ReadMode) -> Result<u64>;
+    async fn fee(&self, mode: ReadMode) -> Result<u64>;
+    async fn metadata(&self, mode: ReadMode) -> Result<Vec<(String, MetadataValue)>>;
+
+    // Send a transfer exactly once. As with the legacy ledger, resending the same request
+    // returns the block index of the original transfer.
+    async fn transfer(&self, request: &TransferRequest) -> Result<u64>;
+}
+
+// ICRC-2 approvals, so another principal can move tokens on our behalf (and vice versa).
+#[async_trait]
+pub trait Icrc2Service: Service {
+    async fn approve(&self, request: &ApproveRequest) -> Result<u64>;
+    async fn transfer_from(&self, request: &TransferFromRequest) -> Result<u64>;
+}
+
+pub struct Agent<'a> {
+    pub agent: &'a ic_agent::Agent,
+    pub canister_id: Principal,
+    pub retry: retry::Policy,
+}
+
+#[derive(CandidType, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetadataValue {
+    Nat(Nat),
+    Int(Int),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferRequest {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount_e8s: u64,
+    pub memo: u64,
+    // Nanoseconds since the unix epoch
+    pub created_at_time: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApproveRequest {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub spender: Account,
+    pub amount_e8s: u64,
+    pub expected_allowance_e8s: Option<u64>,
+    pub expires_at: Option<u64>,
+    pub memo: u64,
+    pub created_at_time: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferFromRequest {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount_e8s: u64,
+    pub memo: u64,
+    pub created_at_time: u64,
+}
+
+#[derive(CandidType)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum TransferResult {
+    Ok(Nat),
+    Err(TransferError),
+}
+
+#[derive(CandidType)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApproveError {
+    BadFee { expected_fee: Nat },
+    InsufficientFunds { balance: Nat },
+    AllowanceChanged { current_allowance: Nat },
+    Expired { ledger_time: u64 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum ApproveResult {
+    Ok(Nat),
+    Err(ApproveError),
+}
+
+#[derive(CandidType)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum TransferFromResult {
+    Ok(Nat),
+    Err(TransferFromError),
+}
+
+// Errors returned by an ICRC ledger for each kind of update.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    Transfer(TransferError),
+    Approve(ApproveError),
+    TransferFrom(TransferFromError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transfer(err) => write!(f, "icrc1_transfer failed: {:?}", err),
+            Error::Approve(err) => write!(f, "icrc2_approve failed: {:?}", err),
+            Error::TransferFrom(err) => write!(f, "icrc2_transfer_from failed: {:?}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for OracleError {
+    fn from(err: Error) -> Self {
+        OracleError::IcrcLedger(err)
+    }
+}
+
+fn nat_to_u64(n: &Nat) -> Result<u64> {
+    match n.0.to_u64_digits().as_slice() {
+        [] => Ok(0),
+        [x] => Ok(*x),
+        _ => Err(OracleError::Candid(format!("{} does not fit in a u64", n))),
+    }
+}
+
+fn memo_bytes(memo: u64) -> Option<Vec<u8>> {
+    Some(memo.to_be_bytes().to_vec())
+}
+
+impl Agent<'_> {
+    #[instrument(skip(self, arg), fields(canister = %self.canister_id), err)]
+    async fn read(&self, method: &str, arg: Vec<u8>, mode: ReadMode) -> Result<Vec<u8>> {
+        self.retry
+            .retry(method, || {
+                query::read(self.agent, &self.canister_id, method, &arg, mode)
+            })
+            .await
+    }
+
+    // All ICRC updates carry a memo and created_at_time, so the ledger deduplicates them, and
+    // they are always safe to retry.
+    #[instrument(skip(self, arg), fields(canister = %self.canister_id), err)]
+    async fn update(&self, method: &str, arg: Vec<u8>) -> Result<Vec<u8>> {
+        self.retry
+            .retry(method, || async {
+                Ok(self
+                    .agent
+                    .update(&self.canister_id, method)
+                    .with_arg(&arg)
+                    .call_and_wait()
+                    .await?)
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Service for Agent<'_> {
+    async fn balance_of(&self, account: &Account, mode: ReadMode) -> Result<u64> {
+        let response = self.read("icrc1_balance_of", Encode!(account)?, mode).await?;
+        nat_to_u64(&Decode!(response.as_slice(), Nat)?)
+    }
+
+    async fn fee(&self, mode: ReadMode) -> Result<u64> {
+        let response = self.read("icrc1_fee", Encode!()?, mode).await?;
+        nat_to_u64(&Decode!(response.as_slice(), Nat)?)
+    }
+
+    async fn metadata(&self, mode: ReadMode) -> Result<Vec<(String, MetadataValue)>> {
+        let response = self.read("icrc1_metadata", Encode!()?, mode).await?;
+        Ok(Decode!(response.as_slice(), Vec<(String, MetadataValue)>)?)
+    }
+
+    #[instrument(
+        skip_all,
+        fields(canister = %self.canister_id, amount_e8s = request.amount_e8s),
+        err
+    )]
+    async fn transfer(&self, request: &TransferRequest) -> Result<u64> {
+        let arg = Encode!(&TransferArg {
+            from_subaccount: request.from_subaccount.clone(),
+            to: request.to.clone(),
+            amount: Nat::from(request.amount_e8s),
+            fee: None,
+            memo: memo_bytes(request.memo),
+            created_at_time: Some(request.created_at_time),
+        })?;
+        let response = self.update("icrc1_transfer", arg).await?;
+        match Decode!(response.as_slice(), TransferResult)? {
+            TransferResult::Ok(index) => {
+                metrics::ICP_MOVED_E8S
+                    .with_label_values(&["transfer"])
+                    .inc_by(request.amount_e8s);
+                nat_to_u64(&index)
+            }
+            TransferResult::Err(TransferError::Duplicate { duplicate_of }) => {
+                nat_to_u64(&duplicate_of)
+            }
+            TransferResult::Err(err) => Err(Error::Transfer(err).into()),
+        }
+    }
+}
+
+#[async_trait]
+impl Icrc2Service for Agent<'_> {
+    async fn approve(&self, request: &ApproveRequest) -> Result<u64> {
+        let arg = Encode!(&ApproveArgs {
+            from_subaccount: request.from_subaccount.clone(),
+            spender: request.spender.clone(),
+            amount: Nat::from(request.amount_e8s),
+            expected_allowance: request.expected_allowance_e8s.map(Nat::from),
+            expires_at: request.expires_at,
+            fee: None,
+            memo: memo_bytes(request.memo),
+            created_at_time: Some(request.created_at_time),
+        })?;
+        let response = self.update("icrc2_approve", arg).await?;
+        match Decode!(response.as_slice(), ApproveResult)? {
+            ApproveResult::Ok(index) => nat_to_u64(&index),
+            ApproveResult::Err(ApproveError::Duplicate { duplicate_of }) => {
+                nat_to_u64(&duplicate_of)
+            }
+            ApproveResult::Err(err) => Err(Error::Approve(err).into()),
+        }
+    }
+
+    async fn transfer_from(&self, request: &TransferFromRequest) -> Result<u64> {
+        let arg = Encode!(&TransferFromArgs {
+            spender_subaccount: request.spender_subaccount.clone(),
+            from: request.from.clone(),
+            to: request.to.clone(),
+            amount: Nat::from(request.amount_e8s),
+            fee: None,
+            memo: memo_bytes(request.memo),
+            created_at_time: Some(request.created_at_time),
+        })?;
+        let response = self.update("icrc2_transfer_from", arg).await?;
+        match Decode!(response.as_slice(), TransferFromResult)? {
+            TransferFromResult::Ok(index) => nat_to_u64(&index),
+            TransferFromResult::Err(TransferFromError::Duplicate { duplicate_of }) => {
+                nat_to_u64(&duplicate_of)
+            }
+            TransferFromResult::Err(err) => Err(Error::TransferFrom(err).into()),
+        }
+    }
+}
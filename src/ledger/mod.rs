@@ -1,18 +1,109 @@
-use anyhow::anyhow;
 use async_trait::async_trait;
 use candid::{CandidType, Decode, Encode, Principal};
-use icp_ledger::{AccountIdentifier, AccountBalanceArgs, TransferArgs, TransferError};
+use clap::{Args, ValueEnum};
+use icp_ledger::{AccountIdentifier, AccountBalanceArgs, TimeStamp, TransferArgs, TransferError};
 use serde::Deserialize;
+use std::time::SystemTime;
+use tracing::instrument;
+
+use crate::error::{OracleError, Result};
+use crate::metrics;
+use crate::query::{self, ReadMode};
+use crate::retry;
+
+pub mod icrc;
+
+pub const DEFAULT_ICP_LEDGER_CANISTER_ID: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Interface {
+    /// The legacy `transfer` and `account_balance_dfx` endpoints, with AccountIdentifiers
+    Legacy,
+    /// The ICRC-1 `icrc1_transfer` and `icrc1_balance_of` endpoints, with principal + subaccount
+    /// accounts
+    Icrc1,
+}
+
+// The ledgers the oracle uses, and how to talk to each. The stICP ledger only has the ICRC
+// interface.
+#[derive(Args, Debug, Clone)]
+pub struct LedgerArgs {
+    /// Principal of the ICP ledger canister
+    #[arg(
+        long,
+        env = "ORACLE_ICP_LEDGER",
+        default_value = DEFAULT_ICP_LEDGER_CANISTER_ID
+    )]
+    pub icp_ledger: String,
+
+    /// Which interface to use the ICP ledger through
+    #[arg(
+        long,
+        value_enum,
+        env = "ORACLE_ICP_LEDGER_INTERFACE",
+        default_value = "legacy"
+    )]
+    pub icp_ledger_interface: Interface,
+
+    /// Principal of the stICP token ledger canister
+    #[arg(long, env = "ORACLE_STICP_LEDGER")]
+    pub sticp_ledger: Option<String>,
+}
+
+impl LedgerArgs {
+    pub fn icp_ledger(&self) -> Result<Principal> {
+        Principal::from_text(&self.icp_ledger)
+            .map_err(|e| OracleError::Config(format!("--icp-ledger: {e}")))
+    }
+
+    pub fn sticp_ledger(&self) -> Result<Principal> {
+        let Some(id) = &self.sticp_ledger else {
+            return Err(OracleError::Config("--sticp-ledger is required".to_string()));
+        };
+        Principal::from_text(id).map_err(|e| OracleError::Config(format!("--sticp-ledger: {e}")))
+    }
+}
 
 #[async_trait]
 pub trait Service {
-    async fn account_balance(&self, id: AccountIdentifier) -> anyhow::Result<u64>;
-    async fn transfer(&self, to: AccountIdentifier, amount: u64, memo: u64) -> anyhow::Result<u64>;
+    async fn account_balance(&self, id: AccountIdentifier, mode: ReadMode) -> Result<u64>;
+
+    // Send a transfer exactly once. Sending the same request again (within the ledger's 24h
+    // deduplication window) returns the block height of the original transfer, rather than
+    // sending the funds twice.
+    async fn transfer(&self, request: &TransferRequest) -> Result<u64>;
+}
+
+// A transfer, identified by its memo and created_at_time. Keep hold of these to retry a transfer
+// safely.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferRequest {
+    pub to: AccountIdentifier,
+    pub amount_e8s: u64,
+    pub memo: u64,
+    // Nanoseconds since the unix epoch
+    pub created_at_time: u64,
+}
+
+impl TransferRequest {
+    pub fn new(to: AccountIdentifier, amount_e8s: u64, memo: u64) -> Result<Self> {
+        let created_at_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|err| OracleError::Config(err.to_string()))?
+            .as_nanos() as u64;
+        Ok(Self {
+            to,
+            amount_e8s,
+            memo,
+            created_at_time,
+        })
+    }
 }
 
 pub struct Agent<'a> {
     pub agent: &'a ic_agent::Agent,
     pub canister_id: Principal,
+    pub retry: retry::Policy,
 }
 
 #[derive(CandidType, Deserialize)]
@@ -30,39 +121,66 @@ pub enum Result_1 {
 
 #[async_trait]
 impl Service for Agent<'_> {
-    async fn account_balance(&self, id: AccountIdentifier) -> anyhow::Result<u64> {
+    #[instrument(skip(self), fields(canister = %self.canister_id), err)]
+    async fn account_balance(&self, id: AccountIdentifier, mode: ReadMode) -> Result<u64> {
+        let arg = Encode!(&AccountBalanceArgs::new(id))?;
         let response = self
-            .agent
-            .update(&self.canister_id, "account_balance_dfx")
-            .with_arg(&Encode!(&AccountBalanceArgs::new(id))?)
-            .call_and_wait()
+            .retry
+            .retry("account_balance_dfx", || {
+                query::read(self.agent, &self.canister_id, "account_balance_dfx", &arg, mode)
+            })
             .await?;
 
-        let result = Decode!(response.as_slice(), Tokens)
-            .map_err(|err| anyhow!(err))?;
+        let result = Decode!(response.as_slice(), Tokens)?;
         Ok(result.e8s)
     }
 
-    async fn transfer(&self, to: AccountIdentifier, amount: u64, memo: u64) -> anyhow::Result<u64> {
-        let response = self
-            .agent
-            .update(&self.canister_id, "transfer")
-            .with_arg(&Encode!(&TransferArgs {
-                memo: icp_ledger::Memo(memo),
-                amount: icp_ledger::Tokens::from_e8s(amount),
-                fee: icp_ledger::DEFAULT_TRANSFER_FEE,
-                from_subaccount: None,
-                to: to.to_address(),
-                created_at_time: None,
-            })?)
-            .call_and_wait()
-            .await?;
+    #[instrument(
+        skip_all,
+        fields(
+            canister = %self.canister_id,
+            to = %request.to,
+            amount_e8s = request.amount_e8s,
+            memo = request.memo
+        ),
+        err
+    )]
+    async fn transfer(&self, request: &TransferRequest) -> Result<u64> {
+        // Every attempt sends the same memo and created_at_time, so the ledger deduplicates
+        // retries of a transfer which actually went through.
+        let arg = Encode!(&TransferArgs {
+            memo: icp_ledger::Memo(request.memo),
+            amount: icp_ledger::Tokens::from_e8s(request.amount_e8s),
+            fee: icp_ledger::DEFAULT_TRANSFER_FEE,
+            from_subaccount: None,
+            to: request.to.to_address(),
+            created_at_time: Some(TimeStamp::from_nanos_since_unix_epoch(
+                request.created_at_time,
+            )),
+        })?;
+        self.retry
+            .retry("transfer", || async {
+                let response = self
+                    .agent
+                    .update(&self.canister_id, "transfer")
+                    .with_arg(&arg)
+                    .call_and_wait()
+                    .await?;
 
-        let result = Decode!(response.as_slice(), Result_1)
-            .map_err(|err| anyhow!(err))?;
-        match result {
-            Result_1::Ok(height) => Ok(height),
-            Result_1::Err(err) => Err(anyhow!(err)),
-        }
+                let result = Decode!(response.as_slice(), Result_1)?;
+                match result {
+                    Result_1::Ok(height) => {
+                        metrics::ICP_MOVED_E8S
+                            .with_label_values(&["transfer"])
+                            .inc_by(request.amount_e8s);
+                        Ok(height)
+                    }
+                    // This exact transfer already went through, either in a previous attempt or a
+                    // previous run.
+                    Result_1::Err(TransferError::TxDuplicate { duplicate_of }) => Ok(duplicate_of),
+                    Result_1::Err(err) => Err(err.into()),
+                }
+            })
+            .await
     }
 }
@@ -0,0 +1,50 @@
+use clap::{Args, ValueEnum};
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+
+use crate::error::{OracleError, Result};
+
+#[derive(Args, Debug, Clone)]
+pub struct LogArgs {
+    /// Format of the log lines written to stderr. JSON puts each event on one line, along with
+    /// the fields of the spans it happened in
+    #[arg(
+        long,
+        global = true,
+        env = "ORACLE_LOG_FORMAT",
+        value_enum,
+        default_value = "human"
+    )]
+    pub log_format: LogFormat,
+
+    /// Minimum level to log, or a filter per module, e.g. "info" or "oracle=debug,ic_agent=warn"
+    #[arg(long, global = true, env = "ORACLE_LOG", default_value = "info")]
+    pub log_level: String,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Human,
+    Json,
+}
+
+impl LogArgs {
+    // Install the global subscriber. Spans log an event when they close, with how long they took,
+    // so every daily-run step and canister call is timed.
+    pub fn init(&self) -> Result<()> {
+        let filter = EnvFilter::try_new(&self.log_level)
+            .map_err(|e| OracleError::Config(format!("Bad --log-level {}: {e}", self.log_level)))?;
+        let builder = tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(std::io::stderr)
+            .with_span_events(FmtSpan::CLOSE);
+        let result = match self.log_format {
+            LogFormat::Human => builder.try_init(),
+            LogFormat::Json => builder
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .try_init(),
+        };
+        result.map_err(|e| OracleError::Config(format!("Couldn't set up logging: {e}")))
+    }
+}
@@ -1,24 +1,71 @@
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
 
+mod alert;
+mod approval;
 mod commands;
+mod config;
 mod deposits;
+mod error;
+mod file;
 mod governance;
 mod identity;
+mod journal;
 mod ledger;
+mod logging;
+mod metrics;
+mod query;
+mod retry;
+#[cfg(test)]
+mod simulation;
+mod strategy;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: commands::Command,
+
+    #[command(flatten)]
+    config: config::ConfigArgs,
+
+    #[command(flatten)]
+    log: logging::LogArgs,
+}
+
+// Parse the command line, with the config file's settings as defaults, and set up logging.
+fn setup() -> error::Result<(Cli, ArgMatches, config::Config)> {
+    let config = config::Config::load()?;
+    let matches = config.apply(Cli::command())?.get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    config.check(&cli.config)?;
+    cli.log.init()?;
+    Ok((cli, matches, config))
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
-    match &cli.command {
-        commands::Command::Daily(c) => c.run().await?,
-        commands::Command::MakeNeuron(c) => c.run().await?,
+async fn main() {
+    let (cli, matches, config) = setup().unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        std::process::exit(err.exit_code());
+    });
+    metrics::register();
+    let result = match &cli.command {
+        commands::Command::Approve(c) => c.run().await,
+        commands::Command::Check(c) => c.run().await,
+        commands::Command::Config(c) => c.run(&config, &matches),
+        commands::Command::Daily(c) => c.run().await,
+        commands::Command::EncryptKey(c) => c.run().await,
+        commands::Command::MakeNeuron(c) => c.run().await,
+        commands::Command::Neurons(c) => c.run().await,
+        commands::Command::Reconcile(c) => c.run().await,
+        commands::Command::Serve(c) => c.run().await,
+        commands::Command::Sticp(c) => c.run().await,
+        commands::Command::TestAlert(c) => c.run().await,
+    };
+    if let Err(err) = result {
+        // Exit with a distinct code per failure class, so callers can tell e.g. a transport
+        // failure from a governance error.
+        tracing::error!("{:?}", err);
+        std::process::exit(error::exit_code(&err));
     }
-    Ok(())
 }
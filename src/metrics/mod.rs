@@ -0,0 +1,125 @@
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    TextEncoder,
+};
+use std::path::Path;
+
+use crate::file;
+
+// How long each step of the daily job took: disburse, refresh, review, split, dissolve, replace.
+pub static STEP_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "oracle_step_duration_seconds",
+        "Duration of each step of the daily job",
+        &["step"],
+        vec![0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+    )
+    .unwrap()
+});
+
+// Neurons acted on: disbursed, split, or replaced.
+pub static NEURONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "oracle_neurons_total",
+        "Neurons disbursed, split or replaced",
+        &["action"]
+    )
+    .unwrap()
+});
+
+pub static SPLIT_E8S: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("oracle_split_e8s_total", "Total e8s split off of neurons").unwrap()
+});
+
+// ICP moved, by how: disburse, split, or transfer.
+pub static ICP_MOVED_E8S: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "oracle_icp_moved_e8s_total",
+        "Total e8s moved by disbursing, splitting and transferring",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+pub static CALLS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "oracle_canister_calls_total",
+        "Canister call attempts, by method",
+        &["method"]
+    )
+    .unwrap()
+});
+
+pub static CALL_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "oracle_canister_call_failures_total",
+        "Failed canister call attempts, by method",
+        &["method"]
+    )
+    .unwrap()
+});
+
+pub static SIGNER_LATENCY: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "oracle_signer_latency_seconds",
+        "Time taken by the remote signer to produce a signature",
+        vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]
+    )
+    .unwrap()
+});
+
+pub static LAST_SUCCESS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "oracle_last_success_timestamp_seconds",
+        "When the daily job last completed successfully"
+    )
+    .unwrap()
+});
+
+// Count one attempt at a canister call.
+pub fn record_call(method: &str, ok: bool) {
+    CALLS.with_label_values(&[method]).inc();
+    if !ok {
+        CALL_FAILURES.with_label_values(&[method]).inc();
+    }
+}
+
+pub fn record_success(at: u64) {
+    LAST_SUCCESS.set(at as i64);
+}
+
+// Register every metric, with the label values the daily job uses, so that every series is
+// exported even by a run which fails before reaching them.
+pub fn register() {
+    for step in ["disburse", "refresh", "review", "split", "dissolve", "replace"] {
+        STEP_DURATION.with_label_values(&[step]);
+    }
+    for action in ["disbursed", "split", "replaced"] {
+        NEURONS.with_label_values(&[action]);
+    }
+    for kind in ["disburse", "split", "transfer"] {
+        ICP_MOVED_E8S.with_label_values(&[kind]);
+    }
+    Lazy::force(&SPLIT_E8S);
+    Lazy::force(&CALLS);
+    Lazy::force(&CALL_FAILURES);
+    Lazy::force(&SIGNER_LATENCY);
+    Lazy::force(&LAST_SUCCESS);
+}
+
+// All metrics, in the Prometheus text format.
+pub fn encode() -> Vec<u8> {
+    let mut buffer = vec![];
+    let _ = TextEncoder::new().encode(&prometheus::gather(), &mut buffer);
+    buffer
+}
+
+// Write all metrics for node_exporter's textfile collector. Written atomically, so the collector
+// never reads a partial file.
+pub fn write_textfile(path: &Path) -> anyhow::Result<()> {
+    file::write_atomic(path, &encode())
+        .with_context(|| format!("Couldn't write {}", path.display()))
+}
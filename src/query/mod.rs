@@ -0,0 +1,35 @@
+use candid::Principal;
+use clap::ValueEnum;
+
+use crate::error::Result;
+
+// How to perform a read-only canister call.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ReadMode {
+    /// A query call, answered by a single replica. Fast, but not certified, so a malicious or
+    /// faulty replica could return anything.
+    Query,
+    /// An update call, which goes through consensus and comes back with a certified response.
+    /// Use this when the result drives a mutating decision, such as which neurons to disburse.
+    Certified,
+}
+
+pub async fn read(
+    agent: &ic_agent::Agent,
+    canister_id: &Principal,
+    method: &str,
+    arg: &[u8],
+    mode: ReadMode,
+) -> Result<Vec<u8>> {
+    let response = match mode {
+        ReadMode::Query => agent.query(canister_id, method).with_arg(arg).call().await?,
+        ReadMode::Certified => {
+            agent
+                .update(canister_id, method)
+                .with_arg(arg)
+                .call_and_wait()
+                .await?
+        }
+    };
+    Ok(response)
+}
@@ -0,0 +1,287 @@
+use clap::Args;
+use rand::Rng;
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+use tracing::{debug_span, field, info, warn, Instrument};
+
+use crate::error::{OracleError, Result};
+use crate::governance;
+use crate::metrics;
+
+// Reject code the replica uses for transient system errors, e.g. a subnet being overloaded.
+const SYS_TRANSIENT: u64 = 2;
+
+// How long a signed update stays valid for. Agents sign every update with this expiry, so a
+// message whose response was lost can still execute until it has passed.
+pub const INGRESS_EXPIRY: Duration = Duration::from_secs(5 * 60);
+
+// How far the replica's clock may lag ours, so how much longer a message can still execute.
+const INGRESS_EXPIRY_DRIFT: Duration = Duration::from_secs(60);
+
+#[derive(Args, Debug, Clone)]
+pub struct RetryArgs {
+    /// Maximum number of attempts for each canister call, including the first
+    #[arg(long, env = "ORACLE_RETRY_MAX_ATTEMPTS", default_value = "3")]
+    pub retry_max_attempts: u32,
+
+    /// Backoff before the first retry, in milliseconds. Doubles after each attempt
+    #[arg(long, env = "ORACLE_RETRY_INITIAL_BACKOFF_MS", default_value = "1000")]
+    pub retry_initial_backoff_ms: u64,
+
+    /// Maximum backoff between retries, in milliseconds
+    #[arg(long, env = "ORACLE_RETRY_MAX_BACKOFF_MS", default_value = "30000")]
+    pub retry_max_backoff_ms: u64,
+}
+
+impl RetryArgs {
+    pub fn policy(&self) -> Policy {
+        Policy {
+            max_attempts: self.retry_max_attempts.max(1),
+            initial_backoff: Duration::from_millis(self.retry_initial_backoff_ms),
+            max_backoff: Duration::from_millis(self.retry_max_backoff_ms),
+            ingress_expiry: INGRESS_EXPIRY + INGRESS_EXPIRY_DRIFT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    // How long after being sent a message whose outcome is unknown might still execute.
+    pub ingress_expiry: Duration,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            ingress_expiry: INGRESS_EXPIRY + INGRESS_EXPIRY_DRIFT,
+        }
+    }
+}
+
+impl Policy {
+    // A policy which never retries.
+    pub fn once() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    // Exponential backoff with jitter. Picks a random delay between half and all of the
+    // exponential delay for this attempt, so that concurrent callers don't retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        let millis = exp.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(millis / 2..=millis))
+    }
+
+    // Retry a call which is safe to repeat, e.g. a read, or a command which has no further
+    // effect when applied twice.
+    pub async fn retry<T, F, Fut>(&self, method: &str, mut call: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = attempt_call(method, attempt, call()).await;
+            match result {
+                Err(err) if attempt < self.max_attempts && is_retryable(&err) => {
+                    let delay = self.backoff(attempt);
+                    warn!(
+                        method,
+                        attempt,
+                        max_attempts = self.max_attempts,
+                        delay_ms = delay.as_millis() as u64,
+                        error = %err,
+                        "Call failed, retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Retry a call which must not be repeated if it already took effect, e.g. a neuron split.
+    // Before each retry, `verify` checks whether the previous attempt actually succeeded (the
+    // response may have been lost after the canister executed it), and if so returns its result
+    // instead of calling again.
+    //
+    // When the outcome is unknown, the previous message may still be waiting to execute, so
+    // `verify` only runs once that message has expired. Otherwise it could execute after
+    // `verify` saw nothing, and again as the retry.
+    pub async fn retry_verified<T, F, Fut, V, VFut>(
+        &self,
+        method: &str,
+        mut call: F,
+        mut verify: V,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+        V: FnMut() -> VFut,
+        VFut: Future<Output = Result<Option<T>>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let sent_at = Instant::now();
+            let result = attempt_call(method, attempt, call()).await;
+            match result {
+                Err(err) if attempt < self.max_attempts && is_retryable(&err) => {
+                    let mut delay = self.backoff(attempt);
+                    if is_unknown_outcome(&err) {
+                        let expired_at = sent_at + self.ingress_expiry;
+                        delay = delay.max(expired_at.saturating_duration_since(Instant::now()));
+                    }
+                    warn!(
+                        method,
+                        attempt,
+                        max_attempts = self.max_attempts,
+                        delay_ms = delay.as_millis() as u64,
+                        error = %err,
+                        "Call failed, verifying before retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    // If we can't even verify, give up with the original error rather than risk
+                    // applying the call twice.
+                    match verify().await {
+                        Ok(Some(result)) => {
+                            info!(method, "Call had already succeeded");
+                            return Ok(result);
+                        }
+                        Ok(None) => {}
+                        Err(verify_err) => {
+                            warn!(method, error = %verify_err, "Couldn't verify call");
+                            return Err(err);
+                        }
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+// Make one attempt at a call, in a span recording its outcome, so the span's close event has the
+// method, attempt, duration and outcome of every call made.
+async fn attempt_call<T>(
+    method: &str,
+    attempt: u32,
+    call: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let span = debug_span!("call", method, attempt, outcome = field::Empty);
+    let result = call.instrument(span.clone()).await;
+    metrics::record_call(method, result.is_ok());
+    span.record("outcome", if result.is_ok() { "ok" } else { "error" });
+    result
+}
+
+// Whether an error might go away by itself. Canister rejects and governance/ledger errors are
+// usually permanent, so only transport failures and transient system errors are retried.
+pub fn is_retryable(err: &OracleError) -> bool {
+    match err {
+        OracleError::Transport(_) => true,
+        OracleError::Reject { code, .. } => *code == SYS_TRANSIENT,
+        OracleError::Governance {
+            error: governance::Error::Unavailable(_) | governance::Error::LedgerUpdateOngoing(_),
+            ..
+        } => true,
+        _ => false,
+    }
+}
+
+// Whether a failed call might still execute. Governance errors are replies, so the call ran and
+// failed, but a transport error or a system reject may have left the message waiting to run.
+fn is_unknown_outcome(err: &OracleError) -> bool {
+    matches!(err, OracleError::Transport(_) | OracleError::Reject { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    fn policy(ingress_expiry: Duration) -> Policy {
+        Policy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            ingress_expiry,
+        }
+    }
+
+    fn transport_error() -> OracleError {
+        OracleError::Transport("connection reset".to_string())
+    }
+
+    // The first message's response is lost, but it's applied a little later, before it expires.
+    // Verifying straight away would see nothing and send it again.
+    #[tokio::test]
+    async fn retry_verified_waits_for_a_late_call() {
+        let sent = Arc::new(AtomicU32::new(0));
+        let applied = Arc::new(AtomicU32::new(0));
+        let result = policy(Duration::from_millis(200))
+            .retry_verified(
+                "split",
+                || {
+                    let applied = applied.clone();
+                    sent.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        tokio::spawn(async move {
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            applied.fetch_add(1, Ordering::SeqCst);
+                        });
+                        Err(transport_error())
+                    }
+                },
+                || {
+                    let applied = applied.clone();
+                    async move { Ok((applied.load(Ordering::SeqCst) > 0).then_some(())) }
+                },
+            )
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+        assert_eq!(applied.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_verified_resends_a_call_which_never_ran() {
+        let sent = Arc::new(AtomicU32::new(0));
+        let result = policy(Duration::ZERO)
+            .retry_verified(
+                "split",
+                || {
+                    let attempt = sent.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        if attempt == 0 {
+                            Err(transport_error())
+                        } else {
+                            Ok(())
+                        }
+                    }
+                },
+                || async { Ok(None) },
+            )
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(sent.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,184 @@
+use async_trait::async_trait;
+use ic_nns_governance::pb::v1::neuron::DissolveState;
+use icp_ledger::AccountIdentifier;
+use std::sync::{Arc, Mutex};
+
+use super::{lock, World, MIN_STAKE_E8S};
+use crate::deposits::{Neuron, Service};
+use crate::error::{OracleError, Result};
+use crate::governance::ICP_FEE;
+use crate::query::ReadMode;
+
+// The deposits canister, reduced to the bookkeeping the daily job depends on: which neurons are
+// staking or being withdrawn, what users are owed, and how much to split off to pay them.
+#[derive(Clone)]
+pub struct Deposits {
+    pub world: Arc<Mutex<World>>,
+}
+
+impl Deposits {
+    // A user asking to withdraw, to be paid once enough neurons have dissolved.
+    pub fn request_withdrawal(&self, amount_e8s: u64) {
+        lock(&self.world).owed_e8s += amount_e8s;
+    }
+}
+
+#[async_trait]
+impl Service for Deposits {
+    async fn list_neurons_to_disburse(&self, now: u64, mode: ReadMode) -> Result<Vec<u64>> {
+        Ok(self
+            .list_withdrawal_neurons(mode)
+            .await?
+            .iter()
+            .filter(|n| {
+                matches!(
+                    n.dissolve_state,
+                    Some(DissolveState::WhenDissolvedTimestampSeconds(ts)) if ts <= now
+                )
+            })
+            .map(|n| n.id)
+            .collect())
+    }
+
+    async fn refresh_neurons_and_apply_interest(&self) -> Result<Vec<(u64, u64, bool)>> {
+        let mut world = lock(&self.world);
+
+        // Forget disbursed neurons, and pick up any new dissolving ones as withdrawal neurons.
+        let World {
+            neurons,
+            staking,
+            withdrawal,
+            ..
+        } = &mut *world;
+        withdrawal.retain(|id| neurons.contains_key(id));
+        for (id, neuron) in neurons.iter() {
+            if neuron.is_dissolving() && !staking.contains(id) && !withdrawal.contains(id) {
+                withdrawal.push(*id);
+            }
+        }
+
+        // Apply interest, by merging each staking neuron's maturity into its stake.
+        let mut interest_e8s: u128 = 0;
+        let mut staked_e8s: u128 = 0;
+        for id in world.staking.clone() {
+            if let Some(neuron) = world.neurons.get_mut(&id) {
+                interest_e8s += neuron.maturity_e8s as u128;
+                neuron.stake_e8s += neuron.maturity_e8s;
+                neuron.maturity_e8s = 0;
+                staked_e8s += neuron.stake_e8s as u128;
+            }
+        }
+        if staked_e8s > 0 {
+            world.apr_microbips = (interest_e8s * 365 * 10_000_000_000 / staked_e8s) as u64;
+        }
+
+        // Pay what's owed from the canister's balance, and stake the rest.
+        let account = World::deposits_account();
+        let balance = world.balance(&account);
+        let pay_e8s = world.owed_e8s.min(balance.saturating_sub(ICP_FEE));
+        if pay_e8s > 0 {
+            let now = world.now;
+            world.transfer(account, World::users_account(), pay_e8s, 0, now)?;
+            world.owed_e8s -= pay_e8s;
+            world.paid_e8s += pay_e8s;
+        }
+        let balance = world.balance(&account);
+        if let (Some(id), true) = (world.staking.first().copied(), balance > ICP_FEE) {
+            world.balances.insert(account, 0);
+            world.burned_e8s += ICP_FEE;
+            if let Some(neuron) = world.neurons.get_mut(&id) {
+                neuron.stake_e8s += balance - ICP_FEE;
+            }
+        }
+
+        Ok(neurons_to_split(&world))
+    }
+
+    async fn preview_neurons_to_split(&self) -> Result<Vec<(u64, u64, bool)>> {
+        Ok(neurons_to_split(&lock(&self.world)))
+    }
+
+    async fn replace_staking_neuron(&self, old_id: u64, new_id: u64) -> Result<()> {
+        let mut world = lock(&self.world);
+        let Some(index) = world.staking.iter().position(|id| *id == old_id) else {
+            return Err(OracleError::Reject {
+                code: 5,
+                message: format!("Neuron {} is not a staking neuron", old_id),
+            });
+        };
+        world.staking[index] = new_id;
+        Ok(())
+    }
+
+    async fn list_staking_neurons(&self, _mode: ReadMode) -> Result<Vec<u64>> {
+        Ok(lock(&self.world).staking.clone())
+    }
+
+    async fn list_withdrawal_neurons(&self, _mode: ReadMode) -> Result<Vec<Neuron>> {
+        let world = lock(&self.world);
+        Ok(world
+            .withdrawal
+            .iter()
+            .filter_map(|id| {
+                let neuron = world.neurons.get(id)?;
+                Some(Neuron {
+                    id: *id,
+                    account_id: vec![],
+                    dissolve_state: Some(neuron.dissolve_state.clone()),
+                    cached_neuron_stake_e8s: neuron.stake_e8s,
+                    staked_maturity_e8s_equivalent: None,
+                })
+            })
+            .collect())
+    }
+
+    // The cache is the world itself, so is always fresh.
+    async fn refresh_neurons(&self, _ids: Vec<u64>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn apr_microbips(&self) -> Result<u64> {
+        Ok(lock(&self.world).apr_microbips)
+    }
+
+    fn account_id(&self) -> Result<AccountIdentifier> {
+        Ok(World::deposits_account())
+    }
+}
+
+// Split enough off the largest staking neurons for the dissolving neurons to cover what's owed.
+// Taking more than half a neuron splits off the part to keep staking instead, and replaces it.
+fn neurons_to_split(world: &World) -> Vec<(u64, u64, bool)> {
+    let dissolving_e8s: u64 = world
+        .withdrawal
+        .iter()
+        .filter_map(|id| world.neurons.get(id))
+        .map(|n| n.stake_e8s)
+        .sum();
+    let mut needed = world.owed_e8s.saturating_sub(dissolving_e8s);
+
+    let mut staking: Vec<(u64, u64)> = world
+        .staking
+        .iter()
+        .filter_map(|id| world.neurons.get(id).map(|n| (*id, n.stake_e8s)))
+        .collect();
+    staking.sort_by_key(|(id, stake_e8s)| (std::cmp::Reverse(*stake_e8s), *id));
+
+    let mut splits = vec![];
+    for (id, stake_e8s) in staking {
+        if needed < MIN_STAKE_E8S {
+            break;
+        }
+        let take = needed.min(stake_e8s.saturating_sub(MIN_STAKE_E8S + ICP_FEE));
+        if take < MIN_STAKE_E8S {
+            continue;
+        }
+        if take > stake_e8s / 2 {
+            splits.push((id, stake_e8s - take, true));
+        } else {
+            splits.push((id, take + ICP_FEE, false));
+        }
+        needed -= take;
+    }
+    splits
+}
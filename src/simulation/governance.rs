@@ -0,0 +1,181 @@
+use async_trait::async_trait;
+use candid::Principal;
+use ic_base_types::PrincipalId;
+use ic_nns_governance::pb::v1::{neuron::DissolveState, Neuron};
+use icp_ledger::AccountIdentifier;
+use std::sync::{Arc, Mutex};
+
+use super::{lock, NeuronState, World, MIN_STAKE_E8S};
+use crate::approval;
+use crate::error::{OracleError, Result};
+use crate::governance::{Error, Service, ICP_FEE};
+use crate::query::ReadMode;
+
+// Governance, enforcing the same rules as the real canister for the commands the oracle sends.
+#[derive(Clone)]
+pub struct Governance {
+    pub world: Arc<Mutex<World>>,
+}
+
+#[async_trait]
+impl Service for Governance {
+    async fn disburse_neurons(&self, address: &AccountIdentifier, neurons: &[u64]) -> Result<()> {
+        let mut world = lock(&self.world);
+        for id in neurons.iter() {
+            let neuron = neuron(&world, *id)?;
+            if !neuron.is_dissolved(world.now) {
+                return Err(OracleError::governance(
+                    Some(*id),
+                    Error::RequiresDissolved("Neuron is not dissolved".to_string()),
+                ));
+            }
+            // Any maturity goes too, as if it had been staked first.
+            let total_e8s = neuron.stake_e8s + neuron.maturity_e8s;
+            let fee_e8s = total_e8s.min(ICP_FEE);
+            world.neurons.remove(id);
+            *world.balances.entry(*address).or_default() += total_e8s - fee_e8s;
+            world.burned_e8s += fee_e8s;
+            world.disbursed.push((*id, *address, total_e8s - fee_e8s));
+        }
+        Ok(())
+    }
+
+    async fn split_neuron(&self, neuron_id: u64, amount_e8s: u64) -> Result<u64> {
+        let mut world = lock(&self.world);
+        let parent = neuron(&world, neuron_id)?;
+        if amount_e8s < MIN_STAKE_E8S + ICP_FEE || parent.stake_e8s < MIN_STAKE_E8S + amount_e8s {
+            return Err(OracleError::governance(
+                Some(neuron_id),
+                Error::InsufficientFunds(format!(
+                    "Splitting {} e8s off a neuron with {} e8s leaves a neuron below the minimum \
+                     stake",
+                    amount_e8s, parent.stake_e8s
+                )),
+            ));
+        }
+        let child = NeuronState {
+            stake_e8s: amount_e8s - ICP_FEE,
+            maturity_e8s: 0,
+            ..parent.clone()
+        };
+        world
+            .neurons
+            .get_mut(&neuron_id)
+            .expect("neuron was just found")
+            .stake_e8s -= amount_e8s;
+        world.burned_e8s += ICP_FEE;
+        Ok(world.create_neuron(child))
+    }
+
+    async fn start_dissolving(&self, neuron_id: u64) -> Result<()> {
+        let mut world = lock(&self.world);
+        let now = world.now;
+        let neuron = neuron_mut(&mut world, neuron_id)?;
+        let DissolveState::DissolveDelaySeconds(delay) = neuron.dissolve_state else {
+            return Err(OracleError::governance(
+                Some(neuron_id),
+                Error::RequiresNotDissolving("Neuron is already dissolving".to_string()),
+            ));
+        };
+        neuron.dissolve_state = DissolveState::WhenDissolvedTimestampSeconds(now + delay);
+        neuron.aging_since_timestamp_seconds = u64::MAX;
+        Ok(())
+    }
+
+    // Claims whatever has been sent to the governance account as a new neuron.
+    async fn claim_neuron(&self, _controller: Option<Principal>, _memo: u64) -> Result<u64> {
+        let mut world = lock(&self.world);
+        let stake_e8s = world
+            .balances
+            .remove(&World::governance_account())
+            .unwrap_or(0);
+        if stake_e8s < MIN_STAKE_E8S {
+            return Err(OracleError::governance(
+                None,
+                Error::InsufficientFunds(format!(
+                    "Account only has {} e8s, less than the minimum stake",
+                    stake_e8s
+                )),
+            ));
+        }
+        let now = world.now;
+        Ok(world.create_neuron(NeuronState {
+            stake_e8s,
+            maturity_e8s: 0,
+            dissolve_state: DissolveState::DissolveDelaySeconds(0),
+            aging_since_timestamp_seconds: now,
+            hot_keys: vec![],
+            auto_stake_maturity: false,
+        }))
+    }
+
+    async fn increase_neuron_delay(
+        &self,
+        neuron_id: u64,
+        additional_dissolve_delay_seconds: u32,
+    ) -> Result<()> {
+        let mut world = lock(&self.world);
+        let neuron = neuron_mut(&mut world, neuron_id)?;
+        neuron.dissolve_state = match neuron.dissolve_state {
+            DissolveState::DissolveDelaySeconds(delay) => DissolveState::DissolveDelaySeconds(
+                delay + additional_dissolve_delay_seconds as u64,
+            ),
+            DissolveState::WhenDissolvedTimestampSeconds(ts) => {
+                DissolveState::WhenDissolvedTimestampSeconds(
+                    ts + additional_dissolve_delay_seconds as u64,
+                )
+            }
+        };
+        Ok(())
+    }
+
+    async fn add_hotkey(&self, neuron_id: u64, key: Principal) -> Result<()> {
+        let mut world = lock(&self.world);
+        let neuron = neuron_mut(&mut world, neuron_id)?;
+        let key = PrincipalId(key);
+        if neuron.hot_keys.contains(&key) {
+            return Err(OracleError::governance(
+                Some(neuron_id),
+                Error::HotKey("Hot key already present".to_string()),
+            ));
+        }
+        neuron.hot_keys.push(key);
+        Ok(())
+    }
+
+    async fn enable_auto_merge_maturity(&self, neuron_id: u64) -> Result<()> {
+        let mut world = lock(&self.world);
+        neuron_mut(&mut world, neuron_id)?.auto_stake_maturity = true;
+        Ok(())
+    }
+
+    // Every neuron is readable, so this is all of them.
+    async fn list_neurons(&self, _neuron_ids: Vec<u64>, _mode: ReadMode) -> Result<Vec<Neuron>> {
+        let world = lock(&self.world);
+        Ok(world
+            .neurons
+            .iter()
+            .map(|(id, n)| n.to_neuron(*id))
+            .collect())
+    }
+
+    fn account_id(&self) -> Result<AccountIdentifier> {
+        Ok(World::governance_account())
+    }
+
+    fn check_approval(&self, _operation: &approval::Operation) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn neuron(world: &World, id: u64) -> Result<NeuronState> {
+    world.neurons.get(&id).cloned().ok_or_else(|| not_found(id))
+}
+
+fn neuron_mut(world: &mut World, id: u64) -> Result<&mut NeuronState> {
+    world.neurons.get_mut(&id).ok_or_else(|| not_found(id))
+}
+
+fn not_found(id: u64) -> OracleError {
+    OracleError::governance(Some(id), Error::NotFound("Neuron not found".to_string()))
+}
@@ -0,0 +1,32 @@
+use async_trait::async_trait;
+use icp_ledger::AccountIdentifier;
+use std::sync::{Arc, Mutex};
+
+use super::{lock, World};
+use crate::error::Result;
+use crate::ledger::{Service, TransferRequest};
+use crate::query::ReadMode;
+
+// The ICP ledger, sending from `from`.
+#[derive(Clone)]
+pub struct Ledger {
+    pub world: Arc<Mutex<World>>,
+    pub from: AccountIdentifier,
+}
+
+#[async_trait]
+impl Service for Ledger {
+    async fn account_balance(&self, id: AccountIdentifier, _mode: ReadMode) -> Result<u64> {
+        Ok(lock(&self.world).balance(&id))
+    }
+
+    async fn transfer(&self, request: &TransferRequest) -> Result<u64> {
+        lock(&self.world).transfer(
+            self.from,
+            request.to,
+            request.amount_e8s,
+            request.memo,
+            request.created_at_time,
+        )
+    }
+}
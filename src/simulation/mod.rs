@@ -0,0 +1,282 @@
+use ic_base_types::PrincipalId;
+use ic_nns_common::pb::v1::NeuronId;
+use ic_nns_governance::pb::v1::{neuron::DissolveState, Neuron};
+use icp_ledger::{AccountIdentifier, Tokens, TransferError};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::error::{OracleError, Result};
+use crate::governance::ICP_FEE;
+
+mod deposits;
+mod governance;
+mod ledger;
+mod tests;
+
+pub use deposits::Deposits;
+pub use governance::Governance;
+pub use ledger::Ledger;
+
+pub const DAY: u64 = 24 * 60 * 60;
+
+// Governance's minimum neuron stake.
+pub const MIN_STAKE_E8S: u64 = 100_000_000;
+
+// A neuron, as governance holds it.
+#[derive(Debug, Clone)]
+pub struct NeuronState {
+    pub stake_e8s: u64,
+    pub maturity_e8s: u64,
+    pub dissolve_state: DissolveState,
+    // u64::MAX while dissolving, as governance does.
+    pub aging_since_timestamp_seconds: u64,
+    pub hot_keys: Vec<PrincipalId>,
+    pub auto_stake_maturity: bool,
+}
+
+impl NeuronState {
+    pub fn is_dissolving(&self) -> bool {
+        matches!(
+            self.dissolve_state,
+            DissolveState::WhenDissolvedTimestampSeconds(_)
+        )
+    }
+
+    pub fn is_dissolved(&self, now: u64) -> bool {
+        match self.dissolve_state {
+            DissolveState::WhenDissolvedTimestampSeconds(ts) => ts <= now,
+            DissolveState::DissolveDelaySeconds(delay) => delay == 0,
+        }
+    }
+
+    fn to_neuron(&self, id: u64) -> Neuron {
+        Neuron {
+            id: Some(NeuronId { id }),
+            cached_neuron_stake_e8s: self.stake_e8s,
+            maturity_e8s_equivalent: self.maturity_e8s,
+            dissolve_state: Some(self.dissolve_state.clone()),
+            aging_since_timestamp_seconds: self.aging_since_timestamp_seconds,
+            hot_keys: self.hot_keys.clone(),
+            auto_stake_maturity: Some(self.auto_stake_maturity),
+            ..Default::default()
+        }
+    }
+}
+
+// Everything the in-memory governance, ledger and deposits canisters know, plus the clock and
+// running totals to check them against. The daily job can be run against them for simulated days
+// at a time, without touching a replica.
+#[derive(Debug)]
+pub struct World {
+    pub now: u64,
+    // Yearly maturity staking neurons earn, in basis points of their stake.
+    pub apr_bips: u64,
+
+    // Governance
+    pub neurons: BTreeMap<u64, NeuronState>,
+    next_neuron_id: u64,
+    // Every disburse: the neuron, where it went, and how much.
+    pub disbursed: Vec<(u64, AccountIdentifier, u64)>,
+
+    // Ledger
+    pub balances: HashMap<AccountIdentifier, u64>,
+    // Transfers seen so far, by everything which identifies them, to their block height.
+    transfers: HashMap<(AccountIdentifier, AccountIdentifier, u64, u64, u64), u64>,
+    height: u64,
+
+    // Deposits canister
+    pub staking: Vec<u64>,
+    pub withdrawal: Vec<u64>,
+    // Withdrawals users have asked for and not yet been paid.
+    pub owed_e8s: u64,
+    pub paid_e8s: u64,
+    pub apr_microbips: u64,
+
+    // ICP brought in from outside: starting stakes, and deposits.
+    pub external_e8s: u64,
+    // Maturity paid out by governance.
+    pub minted_e8s: u64,
+    // Fees paid on splits, disburses and transfers.
+    pub burned_e8s: u64,
+}
+
+impl World {
+    pub fn new(now: u64, apr_bips: u64) -> Self {
+        Self {
+            now,
+            apr_bips,
+            neurons: BTreeMap::new(),
+            next_neuron_id: 1,
+            disbursed: vec![],
+            balances: HashMap::new(),
+            transfers: HashMap::new(),
+            height: 0,
+            staking: vec![],
+            withdrawal: vec![],
+            owed_e8s: 0,
+            paid_e8s: 0,
+            apr_microbips: 0,
+            external_e8s: 0,
+            minted_e8s: 0,
+            burned_e8s: 0,
+        }
+    }
+
+    pub fn shared(self) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn deposits_account() -> AccountIdentifier {
+        AccountIdentifier::new(PrincipalId::new_user_test_id(1), None)
+    }
+
+    pub fn governance_account() -> AccountIdentifier {
+        AccountIdentifier::new(PrincipalId::new_user_test_id(2), None)
+    }
+
+    // Where users deposit from and are paid withdrawals to.
+    pub fn users_account() -> AccountIdentifier {
+        AccountIdentifier::new(PrincipalId::new_user_test_id(3), None)
+    }
+
+    // Stake a new neuron from outside the world, as the deposits canister's staking neuron.
+    pub fn add_staking_neuron(
+        &mut self,
+        stake_e8s: u64,
+        dissolve_delay_seconds: u64,
+        age_seconds: u64,
+    ) -> u64 {
+        let id = self.create_neuron(NeuronState {
+            stake_e8s,
+            maturity_e8s: 0,
+            dissolve_state: DissolveState::DissolveDelaySeconds(dissolve_delay_seconds),
+            aging_since_timestamp_seconds: self.now.saturating_sub(age_seconds),
+            hot_keys: vec![],
+            auto_stake_maturity: true,
+        });
+        self.external_e8s += stake_e8s;
+        self.staking.push(id);
+        id
+    }
+
+    fn create_neuron(&mut self, neuron: NeuronState) -> u64 {
+        let id = self.next_neuron_id;
+        self.next_neuron_id += 1;
+        self.neurons.insert(id, neuron);
+        id
+    }
+
+    // Bring ICP into the world, e.g. a user buying some before depositing it.
+    pub fn mint(&mut self, to: AccountIdentifier, amount_e8s: u64) {
+        *self.balances.entry(to).or_default() += amount_e8s;
+        self.external_e8s += amount_e8s;
+    }
+
+    pub fn balance(&self, account: &AccountIdentifier) -> u64 {
+        self.balances.get(account).copied().unwrap_or(0)
+    }
+
+    // Move the clock on, with every locked neuron earning maturity on the way.
+    pub fn advance(&mut self, seconds: u64) {
+        for neuron in self.neurons.values_mut() {
+            if neuron.is_dissolving() || neuron.is_dissolved(self.now) {
+                continue;
+            }
+            let maturity_e8s = (neuron.stake_e8s as u128 * self.apr_bips as u128 * seconds as u128
+                / (10_000 * 365 * DAY as u128)) as u64;
+            neuron.maturity_e8s += maturity_e8s;
+            self.minted_e8s += maturity_e8s;
+        }
+        self.now += seconds;
+    }
+
+    // Send a transfer, charging the fee. Sending the same transfer again returns the original
+    // block height, as the ledger's deduplication does.
+    pub fn transfer(
+        &mut self,
+        from: AccountIdentifier,
+        to: AccountIdentifier,
+        amount_e8s: u64,
+        memo: u64,
+        created_at_time: u64,
+    ) -> Result<u64> {
+        let key = (from, to, amount_e8s, memo, created_at_time);
+        if let Some(height) = self.transfers.get(&key) {
+            return Ok(*height);
+        }
+        let balance = self.balance(&from);
+        if balance < amount_e8s + ICP_FEE {
+            return Err(OracleError::Ledger(TransferError::InsufficientFunds {
+                balance: Tokens::from_e8s(balance),
+            }));
+        }
+        self.balances.insert(from, balance - amount_e8s - ICP_FEE);
+        *self.balances.entry(to).or_default() += amount_e8s;
+        self.burned_e8s += ICP_FEE;
+        self.height += 1;
+        self.transfers.insert(key, self.height);
+        Ok(self.height)
+    }
+
+    // Everything that should always hold between runs, returning what doesn't.
+    pub fn check(&self) -> Vec<String> {
+        let mut violations = vec![];
+
+        // No stake lost: every e8s brought in or minted is still held somewhere, or went on fees.
+        let held: u64 = self
+            .neurons
+            .values()
+            .map(|n| n.stake_e8s + n.maturity_e8s)
+            .sum::<u64>()
+            + self.balances.values().sum::<u64>();
+        if held + self.burned_e8s != self.external_e8s + self.minted_e8s {
+            violations.push(format!(
+                "{} e8s held and {} e8s burned, but {} e8s brought in and {} e8s minted",
+                held, self.burned_e8s, self.external_e8s, self.minted_e8s
+            ));
+        }
+
+        for (id, to, amount_e8s) in self.disbursed.iter() {
+            if *to != Self::deposits_account() {
+                violations.push(format!(
+                    "neuron {} was disbursed to {}, not the deposits account",
+                    id, to
+                ));
+            }
+            if *amount_e8s == 0 {
+                violations.push(format!("neuron {} was disbursed with nothing in it", id));
+            }
+        }
+
+        for id in self.staking.iter() {
+            match self.neurons.get(id) {
+                None => violations.push(format!("staking neuron {} doesn't exist", id)),
+                Some(n) if n.is_dissolving() => {
+                    violations.push(format!("staking neuron {} is dissolving", id))
+                }
+                _ => {}
+            }
+        }
+
+        // Each daily run should disburse anything which finished dissolving since the last one.
+        for (id, neuron) in self.neurons.iter() {
+            if let DissolveState::WhenDissolvedTimestampSeconds(ts) = neuron.dissolve_state {
+                if ts + DAY <= self.now {
+                    violations.push(format!(
+                        "neuron {} dissolved at {} but hasn't been disbursed",
+                        id, ts
+                    ));
+                }
+            }
+        }
+
+        violations
+    }
+}
+
+// Lock the world. Nothing panics while holding it, so a poisoned lock is still consistent.
+pub fn lock(world: &Mutex<World>) -> MutexGuard<'_, World> {
+    world.lock().unwrap_or_else(|err| err.into_inner())
+}
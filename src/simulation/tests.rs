@@ -0,0 +1,259 @@
+use anyhow::{bail, Context};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    fs,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use super::{lock, Deposits, Governance, Ledger, World, DAY};
+use crate::commands::daily;
+use crate::deposits::Service as DepositsService;
+use crate::governance::ICP_FEE;
+use crate::journal::Journal;
+use crate::ledger::{Service as LedgerService, TransferRequest};
+use crate::query::ReadMode;
+use crate::strategy::{Divergence, Preference, Review, StrategyArgs};
+
+// When the simulated clock starts, so every run with the same seed is the same.
+const START: u64 = 1_700_000_000;
+
+// Tests run in parallel, so each scenario gets its own journal.
+static JOURNALS: AtomicUsize = AtomicUsize::new(0);
+
+struct Scenario {
+    // Days of deposits and withdrawals. The scenario then carries on until every withdrawal
+    // neuron has been disbursed.
+    days: u64,
+    neurons: u64,
+    neuron_stake_e8s: u64,
+    // Dissolve delay of the first staking neuron. Each further neuron's is this much longer, and
+    // a month older.
+    dissolve_delay_days: u64,
+    apr_bips: u64,
+    // Averages per day.
+    deposit_e8s: u64,
+    withdrawal_e8s: u64,
+    seed: u64,
+    strategy: StrategyArgs,
+}
+
+impl Scenario {
+    fn new(seed: u64, split_prefer: Preference, split_floor_e8s: u64) -> Self {
+        Self {
+            days: 30,
+            neurons: 4,
+            neuron_stake_e8s: 100_000_000_000,
+            dissolve_delay_days: 7,
+            apr_bips: 1_000,
+            deposit_e8s: 5_000_000_000,
+            withdrawal_e8s: 5_000_000_000,
+            seed,
+            strategy: StrategyArgs {
+                split_prefer,
+                split_floor_e8s,
+                neuron_min_stake_e8s: super::MIN_STAKE_E8S,
+            },
+        }
+    }
+
+    // Run the daily job once a simulated day, checking the world after each run, and return it,
+    // with each run's review of the splits, once every withdrawal neuron has been disbursed.
+    async fn run(&self) -> anyhow::Result<(Arc<Mutex<World>>, Vec<Review>)> {
+        let mut world = World::new(START, self.apr_bips);
+        for i in 0..self.neurons {
+            world.add_staking_neuron(
+                self.neuron_stake_e8s,
+                (i + 1) * self.dissolve_delay_days * DAY,
+                i * 30 * DAY,
+            );
+        }
+        let world = world.shared();
+        let g = Governance {
+            world: world.clone(),
+        };
+        let d = Deposits {
+            world: world.clone(),
+        };
+        let users = Ledger {
+            world: world.clone(),
+            from: World::users_account(),
+        };
+        let deposits_address = d.account_id()?;
+        let strategy = self.strategy.strategy();
+        let mut rng = StdRng::seed_from_u64(self.seed);
+
+        let journal_path = std::env::temp_dir().join(format!(
+            "oracle-simulation-{}-{}.json",
+            std::process::id(),
+            JOURNALS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_file(&journal_path);
+
+        // Long enough for a neuron split on the last day to dissolve, and be disbursed.
+        let last_day = self.days + self.neurons * self.dissolve_delay_days + 1;
+        let mut reviews = vec![];
+        let result = async {
+            for day in 0..=last_day {
+                let now = {
+                    let mut state = lock(&world);
+                    state.advance(DAY);
+                    state.now
+                };
+
+                if day < self.days {
+                    let deposit_e8s = rng.gen_range(0..=2 * self.deposit_e8s);
+                    if deposit_e8s > 0 {
+                        lock(&world).mint(World::users_account(), deposit_e8s + ICP_FEE);
+                        users
+                            .transfer(&TransferRequest {
+                                to: deposits_address,
+                                amount_e8s: deposit_e8s,
+                                memo: day,
+                                created_at_time: now * 1_000_000_000,
+                            })
+                            .await?;
+                    }
+                    d.request_withdrawal(rng.gen_range(0..=2 * self.withdrawal_e8s));
+                }
+
+                let mut journal = Journal::open(&journal_path, now)?;
+                let review = daily::execute(
+                    &d,
+                    &g,
+                    &deposits_address,
+                    &mut journal,
+                    now,
+                    ReadMode::Query,
+                    &strategy,
+                )
+                .await
+                .with_context(|| format!("Daily run failed on day {}", day))?;
+                reviews.extend(review);
+
+                let state = lock(&world);
+                let violations = state.check();
+                if !violations.is_empty() {
+                    bail!("Day {}: {}", day, violations.join("; "));
+                }
+                let dissolving: Vec<u64> = state
+                    .neurons
+                    .iter()
+                    .filter(|(_, n)| n.is_dissolving())
+                    .map(|(id, _)| *id)
+                    .collect();
+                if day >= self.days && dissolving.is_empty() {
+                    return Ok(());
+                }
+                if day == last_day {
+                    bail!(
+                        "Neurons {:?} were never disbursed to the deposits account",
+                        dissolving
+                    );
+                }
+            }
+            Ok(())
+        }
+        .await;
+        let _ = fs::remove_file(&journal_path);
+        result?;
+        Ok((world, reviews))
+    }
+}
+
+async fn assert_drains(scenario: Scenario) -> Vec<Review> {
+    let (world, reviews) = scenario
+        .run()
+        .await
+        .unwrap_or_else(|err| panic!("seed {}: {:#}", scenario.seed, err));
+    let world = lock(&world);
+    assert!(
+        !world.disbursed.is_empty(),
+        "seed {}: nothing was ever disbursed",
+        scenario.seed
+    );
+    assert!(
+        world.paid_e8s > 0,
+        "seed {}: nothing was paid",
+        scenario.seed
+    );
+    reviews
+}
+
+#[tokio::test]
+async fn longest_delay_drains_withdrawals() {
+    for seed in 0..4 {
+        assert_drains(Scenario::new(seed, Preference::LongestDelay, 0)).await;
+    }
+}
+
+#[tokio::test]
+async fn least_age_loss_drains_withdrawals() {
+    for seed in 0..4 {
+        assert_drains(Scenario::new(seed, Preference::LeastAgeLoss, 0)).await;
+    }
+}
+
+// A floor above every staking neuron's stake means the oracle would never split any of them, but
+// the canister's splits still go ahead, with the differences only reported.
+#[tokio::test]
+async fn floor_only_reports_differences() {
+    for seed in 0..4 {
+        let scenario = Scenario::new(seed, Preference::LongestDelay, 100_000_000_000_000);
+        let strategy = scenario.strategy.strategy();
+        let reviews = assert_drains(scenario).await;
+        let divergences: Vec<&Divergence> =
+            reviews.iter().flat_map(|r| r.divergences.iter()).collect();
+        assert!(
+            divergences
+                .iter()
+                .any(|d| matches!(d, Divergence::BelowFloor { .. })),
+            "seed {}: no split was reported below the floor",
+            seed
+        );
+        assert!(
+            divergences
+                .iter()
+                .any(|d| matches!(d, Divergence::Amount { oracle_e8s: 0, .. })),
+            "seed {}: no difference in amounts was reported",
+            seed
+        );
+        for review in reviews.iter() {
+            assert!(
+                strategy.check(review).is_ok(),
+                "seed {}: {:?}",
+                seed,
+                review
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn same_seed_same_world() {
+    let (first, _) = Scenario::new(7, Preference::LongestDelay, 0)
+        .run()
+        .await
+        .unwrap();
+    let (second, _) = Scenario::new(7, Preference::LongestDelay, 0)
+        .run()
+        .await
+        .unwrap();
+    let (first, second) = (lock(&first), lock(&second));
+    assert_eq!(first.now, second.now);
+    assert_eq!(first.disbursed, second.disbursed);
+    assert_eq!(first.paid_e8s, second.paid_e8s);
+    assert_eq!(first.burned_e8s, second.burned_e8s);
+}
+
+#[tokio::test]
+async fn heavy_withdrawals_still_drain() {
+    for seed in 0..3 {
+        let mut scenario = Scenario::new(seed, Preference::LeastAgeLoss, 0);
+        scenario.deposit_e8s = 1_000_000_000;
+        scenario.withdrawal_e8s = 20_000_000_000;
+        assert_drains(scenario).await;
+    }
+}
@@ -0,0 +1,525 @@
+use clap::{Args, ValueEnum};
+use ic_nns_governance::pb::v1::{neuron::DissolveState, Neuron};
+use serde::Serialize;
+use std::{cmp::Reverse, collections::BTreeMap, fmt};
+use tracing::{info, warn};
+
+use crate::deposits::Service as DepositsService;
+use crate::error::{OracleError, Result};
+use crate::governance::{Service as GovernanceService, ICP_FEE};
+use crate::query::ReadMode;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Preference {
+    /// Split the neurons with the longest remaining dissolve delay first
+    LongestDelay,
+    /// Split the youngest neurons first, so the least age bonus is lost
+    LeastAgeLoss,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct StrategyArgs {
+    /// Which staking neurons to split first when working out the splits to compare the deposits
+    /// canister's against
+    #[arg(
+        long,
+        value_enum,
+        env = "ORACLE_SPLIT_PREFER",
+        default_value = "longest-delay"
+    )]
+    pub split_prefer: Preference,
+
+    /// Don't split staking neurons with less stake than this
+    #[arg(long, env = "ORACLE_SPLIT_FLOOR_E8S", default_value = "0")]
+    pub split_floor_e8s: u64,
+
+    /// Governance's minimum neuron stake, which both neurons must have after a split
+    #[arg(long, env = "ORACLE_NEURON_MIN_STAKE_E8S", default_value = "100000000")]
+    pub neuron_min_stake_e8s: u64,
+}
+
+impl StrategyArgs {
+    pub fn strategy(&self) -> Strategy {
+        Strategy {
+            prefer: self.split_prefer,
+            floor_e8s: self.split_floor_e8s,
+            min_stake_e8s: self.neuron_min_stake_e8s,
+        }
+    }
+}
+
+// A staking neuron, as far as splitting it goes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub id: u64,
+    pub stake_e8s: u64,
+    pub dissolve_delay_seconds: u64,
+    pub age_seconds: u64,
+}
+
+impl Candidate {
+    pub fn new(neuron: &Neuron, now: u64) -> Option<Self> {
+        let dissolve_delay_seconds = match neuron.dissolve_state {
+            Some(DissolveState::WhenDissolvedTimestampSeconds(ts)) => ts.saturating_sub(now),
+            Some(DissolveState::DissolveDelaySeconds(delay)) => delay,
+            None => 0,
+        };
+        Some(Self {
+            id: neuron.id.as_ref()?.id,
+            // Governance splits what's left after fees.
+            stake_e8s: neuron
+                .cached_neuron_stake_e8s
+                .saturating_sub(neuron.neuron_fees_e8s),
+            dissolve_delay_seconds,
+            age_seconds: now.saturating_sub(neuron.aging_since_timestamp_seconds),
+        })
+    }
+}
+
+// The deposits canister's staking neurons, as governance currently sees them.
+pub async fn candidates<D, G>(d: &D, g: &G, now: u64) -> Result<Vec<Candidate>>
+where
+    D: DepositsService + Sync,
+    G: GovernanceService + Sync,
+{
+    let ids = d.list_staking_neurons(ReadMode::Certified).await?;
+    Ok(g.list_neurons(ids.clone(), ReadMode::Certified)
+        .await?
+        .iter()
+        .filter_map(|n| Candidate::new(n, now))
+        .filter(|c| ids.contains(&c.id))
+        .collect())
+}
+
+// How a split plan differs from what this strategy would do.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Divergence {
+    // The split is of a neuron which isn't one of the deposits canister's staking neurons.
+    UnknownNeuron {
+        neuron_id: u64,
+    },
+    // Governance would reject the split, as one of the neurons would end up with less than the
+    // minimum stake.
+    BelowMinimumStake {
+        neuron_id: u64,
+        amount_e8s: u64,
+        stake_e8s: u64,
+        min_stake_e8s: u64,
+    },
+    // The neuron has less stake than the configured floor, so would never be split here.
+    BelowFloor {
+        neuron_id: u64,
+        stake_e8s: u64,
+        floor_e8s: u64,
+    },
+    // The two plans withdraw different amounts from the neuron.
+    Amount {
+        neuron_id: u64,
+        canister_e8s: u64,
+        oracle_e8s: u64,
+    },
+    // The staking neurons can't cover the withdrawal without breaking the minimum stake or floor.
+    Shortfall {
+        wanted_e8s: u64,
+        planned_e8s: u64,
+    },
+}
+
+impl Divergence {
+    // Whether governance would reject the split outright.
+    pub fn is_invalid(&self) -> bool {
+        matches!(
+            self,
+            Divergence::UnknownNeuron { .. } | Divergence::BelowMinimumStake { .. }
+        )
+    }
+
+    pub fn neuron_id(&self) -> Option<u64> {
+        match self {
+            Divergence::UnknownNeuron { neuron_id }
+            | Divergence::BelowMinimumStake { neuron_id, .. }
+            | Divergence::BelowFloor { neuron_id, .. }
+            | Divergence::Amount { neuron_id, .. } => Some(*neuron_id),
+            Divergence::Shortfall { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::UnknownNeuron { neuron_id } => {
+                write!(f, "neuron {} is not a staking neuron", neuron_id)
+            }
+            Divergence::BelowMinimumStake {
+                neuron_id,
+                amount_e8s,
+                stake_e8s,
+                min_stake_e8s,
+            } => write!(
+                f,
+                "splitting {} e8s off neuron {} with {} e8s would leave a neuron with less than \
+                 the minimum stake of {} e8s",
+                amount_e8s, neuron_id, stake_e8s, min_stake_e8s
+            ),
+            Divergence::BelowFloor {
+                neuron_id,
+                stake_e8s,
+                floor_e8s,
+            } => write!(
+                f,
+                "neuron {} has {} e8s, below the split floor of {} e8s",
+                neuron_id, stake_e8s, floor_e8s
+            ),
+            Divergence::Amount {
+                neuron_id,
+                canister_e8s,
+                oracle_e8s,
+            } => write!(
+                f,
+                "canister withdraws {} e8s from neuron {}, oracle would withdraw {} e8s",
+                canister_e8s, neuron_id, oracle_e8s
+            ),
+            Divergence::Shortfall {
+                wanted_e8s,
+                planned_e8s,
+            } => write!(
+                f,
+                "oracle could only plan to withdraw {} of {} e8s",
+                planned_e8s, wanted_e8s
+            ),
+        }
+    }
+}
+
+// The deposits canister's splits, the splits computed here for the same withdrawal, and how the
+// two differ.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Review {
+    pub canister: Vec<(u64, u64, bool)>,
+    pub oracle: Vec<(u64, u64, bool)>,
+    pub divergences: Vec<Divergence>,
+}
+
+impl Review {
+    pub fn report(&self) {
+        for divergence in self.divergences.iter() {
+            warn!(divergence = ?divergence, "{}", divergence);
+        }
+        info!(
+            canister_splits = self.canister.len(),
+            oracle_splits = self.oracle.len(),
+            divergences = self.divergences.len(),
+            "Reviewed splits"
+        );
+    }
+}
+
+// Checks the splits the deposits canister asks for, and works out its own to compare them with.
+// Only the canister's splits are ever executed, as the canister has to track every neuron split,
+// so the strategy's own plan is only reported.
+#[derive(Debug, Clone)]
+pub struct Strategy {
+    prefer: Preference,
+    floor_e8s: u64,
+    min_stake_e8s: u64,
+}
+
+impl Strategy {
+    // Check the canister's splits against governance's rules and the floor, and compare them with
+    // the splits this strategy would make for the same withdrawal.
+    pub fn review(&self, canister: &[(u64, u64, bool)], candidates: &[Candidate]) -> Review {
+        let mut divergences = vec![];
+        let mut stakes: BTreeMap<u64, u64> =
+            candidates.iter().map(|c| (c.id, c.stake_e8s)).collect();
+        let mut canister_withdrawals: BTreeMap<u64, u64> = BTreeMap::new();
+        for (id, amount_e8s, should_replace) in canister.iter() {
+            let Some(stake_e8s) = stakes.get_mut(id) else {
+                divergences.push(Divergence::UnknownNeuron { neuron_id: *id });
+                continue;
+            };
+            if *amount_e8s < self.min_stake_e8s + ICP_FEE
+                || stake_e8s.saturating_sub(*amount_e8s) < self.min_stake_e8s
+            {
+                divergences.push(Divergence::BelowMinimumStake {
+                    neuron_id: *id,
+                    amount_e8s: *amount_e8s,
+                    stake_e8s: *stake_e8s,
+                    min_stake_e8s: self.min_stake_e8s,
+                });
+            }
+            if *stake_e8s < self.floor_e8s {
+                divergences.push(Divergence::BelowFloor {
+                    neuron_id: *id,
+                    stake_e8s: *stake_e8s,
+                    floor_e8s: self.floor_e8s,
+                });
+            }
+            // The new neuron gets the amount less the fee. When replacing, the old neuron is the
+            // one withdrawn, with whatever it has left.
+            let withdrawal_e8s = if *should_replace {
+                stake_e8s.saturating_sub(*amount_e8s)
+            } else {
+                amount_e8s.saturating_sub(ICP_FEE)
+            };
+            *canister_withdrawals.entry(*id).or_default() += withdrawal_e8s;
+            // A replaced neuron is withdrawn entirely, and its stake lives on in the new neuron.
+            *stake_e8s = if *should_replace {
+                0
+            } else {
+                stake_e8s.saturating_sub(*amount_e8s)
+            };
+        }
+
+        let wanted_e8s = canister_withdrawals.values().sum();
+        let (oracle, planned_e8s) = self.plan(wanted_e8s, candidates);
+        if planned_e8s < wanted_e8s {
+            divergences.push(Divergence::Shortfall {
+                wanted_e8s,
+                planned_e8s,
+            });
+        }
+
+        let oracle_withdrawals: BTreeMap<u64, u64> = oracle
+            .iter()
+            .map(|(id, amount_e8s, _)| (*id, amount_e8s - ICP_FEE))
+            .collect();
+        let mut ids: Vec<u64> = canister_withdrawals.keys().copied().collect();
+        ids.extend(
+            oracle_withdrawals
+                .keys()
+                .filter(|id| !canister_withdrawals.contains_key(id)),
+        );
+        for id in ids {
+            let canister_e8s = canister_withdrawals.get(&id).copied().unwrap_or(0);
+            let oracle_e8s = oracle_withdrawals.get(&id).copied().unwrap_or(0);
+            if canister_e8s != oracle_e8s {
+                divergences.push(Divergence::Amount {
+                    neuron_id: id,
+                    canister_e8s,
+                    oracle_e8s,
+                });
+            }
+        }
+
+        Review {
+            canister: canister.to_vec(),
+            oracle,
+            divergences,
+        }
+    }
+
+    // Splits withdrawing `wanted_e8s` from the preferred staking neurons, returning them and how
+    // much they withdraw in total, which falls short if the neurons can't cover it. Each split
+    // creates a new neuron to dissolve, and leaves the old one staking.
+    pub fn plan(&self, wanted_e8s: u64, candidates: &[Candidate]) -> (Vec<(u64, u64, bool)>, u64) {
+        let mut neurons: Vec<&Candidate> = candidates
+            .iter()
+            .filter(|c| c.stake_e8s >= self.floor_e8s)
+            .collect();
+        match self.prefer {
+            Preference::LongestDelay => {
+                neurons.sort_by_key(|c| (Reverse(c.dissolve_delay_seconds), c.id))
+            }
+            Preference::LeastAgeLoss => neurons.sort_by_key(|c| (c.age_seconds, c.id)),
+        }
+
+        let mut splits = vec![];
+        let mut remaining = wanted_e8s;
+        for neuron in neurons {
+            if remaining == 0 {
+                break;
+            }
+            // Both the new neuron, after the fee, and what's left of the old one need the
+            // minimum stake.
+            let available = neuron
+                .stake_e8s
+                .saturating_sub(self.min_stake_e8s + ICP_FEE);
+            let mut take = remaining.min(available);
+            // Don't leave a remainder too small for any neuron to be split off for.
+            if remaining > take && remaining - take < self.min_stake_e8s {
+                take = remaining.saturating_sub(self.min_stake_e8s);
+            }
+            if take < self.min_stake_e8s {
+                continue;
+            }
+            splits.push((neuron.id, take + ICP_FEE, false));
+            remaining -= take;
+        }
+        (splits, wanted_e8s - remaining)
+    }
+
+    // Refuse the canister's splits if governance would reject any of them, so none are sent.
+    pub fn check(&self, review: &Review) -> Result<()> {
+        match review.divergences.iter().find(|d| d.is_invalid()) {
+            Some(invalid) => Err(OracleError::SplitRefused(invalid.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ICP: u64 = 100_000_000;
+
+    fn strategy(prefer: Preference, floor_e8s: u64) -> Strategy {
+        Strategy {
+            prefer,
+            floor_e8s,
+            min_stake_e8s: ICP,
+        }
+    }
+
+    fn candidate(
+        id: u64,
+        stake_e8s: u64,
+        dissolve_delay_seconds: u64,
+        age_seconds: u64,
+    ) -> Candidate {
+        Candidate {
+            id,
+            stake_e8s,
+            dissolve_delay_seconds,
+            age_seconds,
+        }
+    }
+
+    #[test]
+    fn plan_prefers_longest_delay() {
+        let candidates = [
+            candidate(1, 10 * ICP, 100, 0),
+            candidate(2, 10 * ICP, 200, 0),
+        ];
+        let (splits, planned) = strategy(Preference::LongestDelay, 0).plan(3 * ICP, &candidates);
+        assert_eq!(splits, vec![(2, 3 * ICP + ICP_FEE, false)]);
+        assert_eq!(planned, 3 * ICP);
+    }
+
+    #[test]
+    fn plan_prefers_least_age_loss() {
+        let candidates = [
+            candidate(1, 10 * ICP, 100, 50),
+            candidate(2, 10 * ICP, 200, 500),
+        ];
+        let (splits, _) = strategy(Preference::LeastAgeLoss, 0).plan(3 * ICP, &candidates);
+        assert_eq!(splits, vec![(1, 3 * ICP + ICP_FEE, false)]);
+    }
+
+    #[test]
+    fn plan_leaves_minimum_stake() {
+        let candidates = [candidate(1, 5 * ICP, 100, 0)];
+        let (splits, planned) = strategy(Preference::LongestDelay, 0).plan(10 * ICP, &candidates);
+        // The parent keeps exactly the minimum stake, the rest goes to the new neuron.
+        assert_eq!(splits, vec![(1, 4 * ICP, false)]);
+        assert_eq!(planned, 4 * ICP - ICP_FEE);
+    }
+
+    #[test]
+    fn plan_skips_neurons_below_floor() {
+        let candidates = [
+            candidate(1, 5 * ICP, 200, 0),
+            candidate(2, 20 * ICP, 100, 0),
+        ];
+        let (splits, _) = strategy(Preference::LongestDelay, 10 * ICP).plan(2 * ICP, &candidates);
+        assert_eq!(splits, vec![(2, 2 * ICP + ICP_FEE, false)]);
+    }
+
+    #[test]
+    fn plan_avoids_unsplittable_remainder() {
+        // Neuron 1 could give 2 ICP, but that would leave 0.5 ICP, less than any split can take.
+        let candidates = [
+            candidate(1, 3 * ICP + ICP_FEE, 200, 0),
+            candidate(2, 20 * ICP, 100, 0),
+        ];
+        let (splits, planned) =
+            strategy(Preference::LongestDelay, 0).plan(5 * ICP / 2, &candidates);
+        assert_eq!(
+            splits,
+            vec![(1, 3 * ICP / 2 + ICP_FEE, false), (2, ICP + ICP_FEE, false)]
+        );
+        assert_eq!(planned, 5 * ICP / 2);
+    }
+
+    #[test]
+    fn review_agrees_with_matching_plan() {
+        let strategy = strategy(Preference::LongestDelay, 0);
+        let candidates = [
+            candidate(1, 10 * ICP, 100, 0),
+            candidate(2, 10 * ICP, 200, 0),
+        ];
+        let review = strategy.review(&[(2, 3 * ICP + ICP_FEE, false)], &candidates);
+        assert_eq!(review.divergences, vec![]);
+        assert!(strategy.check(&review).is_ok());
+    }
+
+    #[test]
+    fn review_compares_replacements_by_amount_withdrawn() {
+        let strategy = strategy(Preference::LongestDelay, 0);
+        let candidates = [candidate(1, 10 * ICP, 100, 0)];
+        // Splitting off 3 ICP to keep staking withdraws the 7 ICP left in the old neuron.
+        let review = strategy.review(&[(1, 3 * ICP, true)], &candidates);
+        assert_eq!(review.oracle, vec![(1, 7 * ICP + ICP_FEE, false)]);
+        assert_eq!(review.divergences, vec![]);
+    }
+
+    #[test]
+    fn review_reports_different_neurons() {
+        let strategy = strategy(Preference::LongestDelay, 0);
+        let candidates = [
+            candidate(1, 10 * ICP, 100, 0),
+            candidate(2, 10 * ICP, 200, 0),
+        ];
+        let review = strategy.review(&[(1, 3 * ICP + ICP_FEE, false)], &candidates);
+        assert_eq!(
+            review.divergences,
+            vec![
+                Divergence::Amount {
+                    neuron_id: 1,
+                    canister_e8s: 3 * ICP,
+                    oracle_e8s: 0,
+                },
+                Divergence::Amount {
+                    neuron_id: 2,
+                    canister_e8s: 0,
+                    oracle_e8s: 3 * ICP,
+                },
+            ]
+        );
+        // Only reported, the canister's splits are still sent.
+        assert!(strategy.check(&review).is_ok());
+    }
+
+    #[test]
+    fn review_refuses_split_below_minimum_stake() {
+        let strategy = strategy(Preference::LongestDelay, 0);
+        let candidates = [candidate(1, 2 * ICP, 100, 0)];
+        let review = strategy.review(&[(1, 3 * ICP / 2, false)], &candidates);
+        assert!(review.divergences.contains(&Divergence::BelowMinimumStake {
+            neuron_id: 1,
+            amount_e8s: 3 * ICP / 2,
+            stake_e8s: 2 * ICP,
+            min_stake_e8s: ICP,
+        }));
+        assert!(matches!(
+            strategy.check(&review),
+            Err(OracleError::SplitRefused(
+                Divergence::BelowMinimumStake { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn review_refuses_unknown_neuron() {
+        let strategy = strategy(Preference::LongestDelay, 0);
+        let review = strategy.review(&[(7, 2 * ICP, false)], &[candidate(1, 10 * ICP, 100, 0)]);
+        assert!(matches!(
+            strategy.check(&review),
+            Err(OracleError::SplitRefused(Divergence::UnknownNeuron {
+                neuron_id: 7
+            }))
+        ));
+    }
+}
@@ -1,16 +1,10 @@
 #!/bin/bash
+set -eo pipefail
 
-canister() {
-    dfx canister --network ic "$@"
-}
+# Settings, including the keystore holding the signer's key, come from the config file passed in,
+# e.g. --config /etc/oracle/oracle.toml.
+echo Checking configuration
+oracle check "$@"
 
-echo Exporting identity
-IDENTITY_PEM="$(mktemp)"
-dfx identity export default > "$IDENTITY_PEM"
-echo pem: "$IDENTITY_PEM"
-
-oracle daily \
-    --private-pem "$IDENTITY_PEM" \
-    --deposits-canister "$(canister id deposits)" \
-    --signing-canister "$(canister id signing)" \
-    --governance "$(canister id nns-governance)"
+echo Running daily job
+oracle daily "$@"